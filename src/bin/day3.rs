@@ -1,101 +1,511 @@
-use std::path::Path;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::Path;
 
-fn read_input() -> String {
+/// Bytes read from the input at a time.
+const CHUNK: usize = 1 << 16;
+
+fn read_input(chunk: usize) -> BufReader<File> {
     let path = Path::new("input/day3.txt");
-    
-    // Open the file
-    let file = File::open(&path).expect("Failed to open file");
-    
-    // Read the entire content as a string
-    let mut content = String::new();
-    file.take(u64::MAX).read_to_string(&mut content).expect("Failed to read file");
-
-    content
-}
-
-fn part1(content: &str) {
-    let mut memories = Vec::new();
-    
-    // Search for "mul(" in the content
-    for i in 0..content.len() {
-        if i + 4 <= content.len() && &content[i..i+4] == "mul(" {
-            // Found "mul(" - now look for closing parenthesis
-            for j in i+4..content.len() {
-                if content.chars().nth(j) == Some(')') {
-                    // Extract content between "mul(" and ")"
-                    let content_str = &content[i+4..j];
-                    let parts: Vec<&str> = content_str.split(',').collect();
-                    
-                    if parts.len() == 2 {
-                        if let (Ok(a), Ok(b)) = (parts[0].parse::<i32>(), parts[1].parse::<i32>()) {
-                            memories.push(vec![a, b]);
-                        }
-                    }
-                    break;
-                }
+    assert!(chunk > 0, "Chunk size must be positive");
+
+    let file = File::open(path).expect("Failed to open file");
+    BufReader::with_capacity(chunk, file)
+}
+
+/// How an instruction argument may be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Param {
+    /// 1 to 3 digits, as in the puzzle.
+    Number,
+    /// A register named by one lowercase letter.
+    Register,
+    /// Either of the above.
+    Value,
+}
+
+/// An argument as written in the memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    Number(u32),
+    Register(usize),
+}
+
+/// An entry in the instruction table: its name, the arguments between its
+/// brackets and what it does to the machine.
+#[derive(Debug)]
+struct Instruction {
+    name: &'static str,
+    params: &'static [Param],
+    /// Runs even while the machine is disabled.
+    always: bool,
+    run: fn(&mut Machine, &[Arg]),
+}
+
+const MUL: Instruction = Instruction {
+    name: "mul",
+    params: &[Param::Number, Param::Number],
+    always: false,
+    run: |m, args| m.acc += m.value(args[0]) * m.value(args[1]),
+};
+
+const ADD: Instruction = Instruction {
+    name: "add",
+    params: &[Param::Value, Param::Value],
+    always: false,
+    run: |m, args| m.acc += m.value(args[0]) + m.value(args[1]),
+};
+
+const SUB: Instruction = Instruction {
+    name: "sub",
+    params: &[Param::Value, Param::Value],
+    always: false,
+    run: |m, args| m.acc += m.value(args[0]) - m.value(args[1]),
+};
+
+const SET: Instruction = Instruction {
+    name: "set",
+    params: &[Param::Register, Param::Value],
+    always: false,
+    run: |m, args| {
+        let value = m.value(args[1]);
+        m.registers[register(args[0])] = value;
+    },
+};
+
+/// Skips the next `n` instructions. Jumps only go forwards, so every
+/// program stops.
+const JMP: Instruction = Instruction {
+    name: "jmp",
+    params: &[Param::Value],
+    always: false,
+    run: |m, args| {
+        let skip = usize::try_from(m.value(args[0])).unwrap_or(0);
+        m.pc = m.pc.saturating_add(skip);
+    },
+};
+
+const DO: Instruction = Instruction {
+    name: "do",
+    params: &[],
+    always: true,
+    run: |m, _| m.enabled = true,
+};
+
+const DONT: Instruction = Instruction {
+    name: "don't",
+    params: &[],
+    always: true,
+    run: |m, _| m.enabled = false,
+};
+
+/// Enables the machine if the register is not zero.
+const DOIF: Instruction = Instruction {
+    name: "doif",
+    params: &[Param::Register],
+    always: true,
+    run: |m, args| {
+        if m.registers[register(args[0])] != 0 {
+            m.enabled = true;
+        }
+    },
+};
+
+/// Disables the machine if the register is not zero.
+const DONTIF: Instruction = Instruction {
+    name: "dontif",
+    params: &[Param::Register],
+    always: true,
+    run: |m, args| {
+        if m.registers[register(args[0])] != 0 {
+            m.enabled = false;
+        }
+    },
+};
+
+/// The instruction sets the machine can be configured with.
+const PART1: &[Instruction] = &[MUL];
+const PART2: &[Instruction] = &[MUL, DO, DONT];
+const FULL: &[Instruction] = &[
+    Instruction {
+        params: &[Param::Value, Param::Value],
+        ..MUL
+    },
+    ADD,
+    SUB,
+    SET,
+    JMP,
+    DO,
+    DONT,
+    DOIF,
+    DONTIF,
+];
+
+fn instruction_set(name: &str) -> Result<&'static [Instruction], String> {
+    match name {
+        "part1" => Ok(PART1),
+        "part2" => Ok(PART2),
+        "full" => Ok(FULL),
+        _ => Err(format!("Unknown instruction set: {}", name)),
+    }
+}
+
+fn register(arg: Arg) -> usize {
+    match arg {
+        Arg::Register(r) => r,
+        Arg::Number(_) => panic!("{:?} is not a register", arg),
+    }
+}
+
+/// An instruction found in the corrupted memory.
+#[derive(Debug, Clone)]
+struct Token {
+    instruction: &'static Instruction,
+    args: Vec<Arg>,
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arg::Number(n) => write!(f, "{}", n),
+            Arg::Register(r) => write!(f, "{}", char::from(b'a' + *r as u8)),
+        }
+    }
+}
+
+/// Writes the instruction back out the way it appears in the memory.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(Arg::to_string).collect();
+        write!(f, "{}({})", self.instruction.name, args.join(","))
+    }
+}
+
+/// A token and the bytes of the input it was read from.
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    span: Range<usize>,
+}
+
+/// Picks the instructions of a set out of the memory in one pass, skipping
+/// everything else a byte at a time. The input is read in chunks, keeping
+/// only what is left of the current chunk plus enough of the next to match
+/// an instruction that straddles the two.
+struct Lexer<R> {
+    input: R,
+    /// Bytes read but not yet scanned past; `bytes[0]` is at `offset` in
+    /// the input.
+    bytes: Vec<u8>,
+    offset: usize,
+    pos: usize,
+    eof: bool,
+    instructions: &'static [Instruction],
+    /// The most bytes matching an instruction can look at.
+    lookahead: usize,
+}
+
+impl<R: BufRead> Lexer<R> {
+    fn new(input: R, instructions: &'static [Instruction]) -> Self {
+        // The name, brackets and commas, up to 3 digits an argument, and one
+        // more byte to see that a number has ended
+        let lookahead = instructions
+            .iter()
+            .map(|instruction| instruction.name.len() + 4 * instruction.params.len() + 2)
+            .max()
+            .unwrap_or(0);
+        Lexer {
+            input,
+            bytes: Vec::new(),
+            offset: 0,
+            pos: 0,
+            eof: false,
+            instructions,
+            lookahead,
+        }
+    }
+
+    /// Reads chunks until `lookahead` bytes are buffered past the current
+    /// position or the input ends, dropping the bytes already scanned.
+    fn fill(&mut self) {
+        while !self.eof && self.bytes.len() - self.pos < self.lookahead {
+            self.bytes.drain(..self.pos);
+            self.offset += self.pos;
+            self.pos = 0;
+
+            let chunk = self.input.fill_buf().expect("Failed to read input");
+            let len = chunk.len();
+            self.bytes.extend_from_slice(chunk);
+            self.input.consume(len);
+            self.eof = len == 0;
+        }
+    }
+
+    /// Consumes `literal` if the input continues with it.
+    fn eat(&mut self, literal: &str) -> bool {
+        let matched = self.bytes[self.pos..].starts_with(literal.as_bytes());
+        if matched {
+            self.pos += literal.len();
+        }
+        matched
+    }
+
+    /// A number of 1 to 3 digits; a fourth digit makes it invalid.
+    fn number(&mut self) -> Option<u32> {
+        let digits = self.bytes[self.pos..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if !(1..=3).contains(&digits) {
+            return None;
+        }
+
+        let value = self.bytes[self.pos..self.pos + digits]
+            .iter()
+            .fold(0, |n, b| n * 10 + u32::from(b - b'0'));
+        self.pos += digits;
+        Some(value)
+    }
+
+    /// A single lowercase letter; whatever follows must be a separator,
+    /// which the caller checks.
+    fn register(&mut self) -> Option<usize> {
+        let b = *self.bytes.get(self.pos)?;
+        if !b.is_ascii_lowercase() {
+            return None;
+        }
+        self.pos += 1;
+        Some(usize::from(b - b'a'))
+    }
+
+    fn arg(&mut self, param: Param) -> Option<Arg> {
+        match param {
+            Param::Number => self.number().map(Arg::Number),
+            Param::Register => self.register().map(Arg::Register),
+            Param::Value => self
+                .number()
+                .map(Arg::Number)
+                .or_else(|| self.register().map(Arg::Register)),
+        }
+    }
+
+    /// `instruction` with its arguments at the current position, if it is
+    /// there. On failure the position is left wherever matching stopped.
+    fn call(&mut self, instruction: &Instruction) -> Option<Vec<Arg>> {
+        if !self.eat(instruction.name) || !self.eat("(") {
+            return None;
+        }
+        let mut args = Vec::with_capacity(instruction.params.len());
+        for (i, &param) in instruction.params.iter().enumerate() {
+            if i > 0 && !self.eat(",") {
+                return None;
             }
+            args.push(self.arg(param)?);
+        }
+        self.eat(")").then_some(args)
+    }
+
+    /// The first instruction of the set found at the current position.
+    fn instruction(&mut self) -> Option<Token> {
+        let start = self.pos;
+        for instruction in self.instructions {
+            self.pos = start;
+            if let Some(args) = self.call(instruction) {
+                return Some(Token { instruction, args });
+            }
+        }
+        None
+    }
+}
+
+impl<R: BufRead> Iterator for Lexer<R> {
+    type Item = Spanned;
+
+    fn next(&mut self) -> Option<Spanned> {
+        loop {
+            self.fill();
+            if self.pos == self.bytes.len() {
+                return None;
+            }
+
+            let start = self.pos;
+            if let Some(token) = self.instruction() {
+                return Some(Spanned {
+                    token,
+                    span: self.offset + start..self.offset + self.pos,
+                });
+            }
+            // A failed match reads at most a few bytes per instruction, so
+            // restarting just after `start` keeps the scan linear
+            self.pos = start + 1;
+        }
+    }
+}
+
+/// Runs the instruction stream with 26 registers `a` to `z` and an
+/// accumulator holding the result.
+#[derive(Debug)]
+struct Machine {
+    registers: [i64; 26],
+    acc: i64,
+    enabled: bool,
+    /// The next instruction to run.
+    pc: usize,
+}
+
+impl Machine {
+    fn new() -> Self {
+        Machine {
+            registers: [0; 26],
+            acc: 0,
+            enabled: true,
+            pc: 0,
+        }
+    }
+
+    fn value(&self, arg: Arg) -> i64 {
+        match arg {
+            Arg::Number(n) => i64::from(n),
+            Arg::Register(r) => self.registers[r],
         }
     }
 
-    // Calculate the sum of products for each memory
-    let sum: i32 = memories.iter()
-        .map(|pair| pair[0] * pair[1])
-        .sum();
-    
-    println!("Day 3 solution - Part 1: {}", sum);
-}
-
-fn part2(content: &str) {
-    let mut memories = Vec::new();
-    let mut enabled = true;
-    let content_len = content.len();
-    
-    // Search for commands in the content
-    for i in 0..content_len {
-        if !enabled && i + 4 <= content_len && &content[i..i+4] == "do()" {
-            // Found "do()" - re-enable processing
-            enabled = true;
-            continue;
-        }
-        
-        if enabled && i + 7 <= content_len && &content[i..i+7] == "don't()" {
-            // Found "don't()" - disable processing
-            enabled = false;
-            continue;
-        }
-        
-        // Process "mul(" only if enabled
-        if enabled && i + 4 <= content_len && &content[i..i+4] == "mul(" {
-            // Found "mul(" - now look for closing parenthesis
-            for j in i+4..content_len {
-                if content.chars().nth(j) == Some(')') {
-                    // Extract content between "mul(" and ")"
-                    let content_str = &content[i+4..j];
-                    let parts: Vec<&str> = content_str.split(',').collect();
-                    
-                    if parts.len() == 2 {
-                        if let (Ok(a), Ok(b)) = (parts[0].parse::<i32>(), parts[1].parse::<i32>()) {
-                            memories.push(vec![a, b]);
-                        }
+    /// Runs the program to the end as it is read, printing every step
+    /// with `trace`. Returns how many instructions there were.
+    fn run(&mut self, program: impl Iterator<Item = Spanned>, trace: bool) -> usize {
+        let mut count = 0;
+        for (index, spanned) in program.enumerate() {
+            count = index + 1;
+            // Jumped over
+            if index < self.pc {
+                continue;
+            }
+
+            self.pc = index + 1;
+            let runs = self.enabled || spanned.token.instruction.always;
+            if runs {
+                (spanned.token.instruction.run)(self, &spanned.token.args);
+            }
+
+            if trace {
+                println!(
+                    "{:>6} {:>8} {:<16} acc {:<10} {}",
+                    index,
+                    spanned.span.start,
+                    spanned.token.to_string(),
+                    self.acc,
+                    match (runs, self.enabled) {
+                        (false, _) => "skipped",
+                        (true, true) => "enabled",
+                        (true, false) => "disabled",
                     }
-                    break;
-                }
+                );
             }
         }
+        count
     }
 
-    // Calculate the sum of products for each memory
-    let sum: i32 = memories.iter()
-        .map(|pair| pair[0] * pair[1])
-        .sum();
-    
-    println!("Day 3 solution - Part 2: {}", sum);
+    /// The registers that are not zero, such as `a=3 c=-1`.
+    fn registers(&self) -> String {
+        self.registers
+            .iter()
+            .enumerate()
+            .filter(|&(_, &value)| value != 0)
+            .map(|(r, value)| format!("{}={}", char::from(b'a' + r as u8), value))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn execute(input: impl BufRead, instructions: &'static [Instruction]) -> i64 {
+    let mut machine = Machine::new();
+    machine.run(Lexer::new(input, instructions), false);
+    machine.acc
+}
+
+fn part1(input: impl BufRead) -> i64 {
+    execute(input, PART1)
+}
+
+fn part2(input: impl BufRead) -> i64 {
+    execute(input, PART2)
 }
 
 fn main() {
-    let content = read_input();
-    part1(content.as_str());
-    part2(content.as_str());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `--chunk BYTES` sets how much of the input is read at a time; every
+    // pass streams the file, so memory use does not grow with its size
+    let chunk =
+        arg_value(&args, "--chunk").map_or(CHUNK, |n| n.parse().expect("Invalid chunk size"));
+
+    println!("Day 3 solution - Part 1: {}", part1(read_input(chunk)));
+    println!("Day 3 solution - Part 2: {}", part2(read_input(chunk)));
+
+    // `--vm part1|part2|full` runs the machine with that instruction set and
+    // prints its registers, `--trace` also prints every step, and `--tokens`
+    // lists every instruction found with the bytes it came from
+    if let Some(name) = arg_value(&args, "--vm") {
+        let instructions = instruction_set(name).unwrap_or_else(|e| panic!("{}", e));
+        if args.iter().any(|a| a == "--tokens") {
+            for spanned in Lexer::new(read_input(chunk), instructions) {
+                println!(
+                    "{:>8}..{:<8} {:<16} {:?}",
+                    spanned.span.start,
+                    spanned.span.end,
+                    spanned.token.to_string(),
+                    spanned.token.args
+                );
+            }
+        }
+
+        let mut machine = Machine::new();
+        let trace = args.iter().any(|a| a == "--trace");
+        let count = machine.run(Lexer::new(read_input(chunk), instructions), trace);
+        println!(
+            "{} instructions, acc {}, registers [{}]",
+            count,
+            machine.acc,
+            machine.registers()
+        );
+    }
+}
+
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|pos| args.get(pos + 1))
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every instruction read with its span, as text so they compare.
+    fn tokens(
+        input: impl BufRead,
+        instructions: &'static [Instruction],
+    ) -> Vec<(String, Range<usize>)> {
+        Lexer::new(input, instructions)
+            .map(|spanned| (spanned.token.to_string(), spanned.span))
+            .collect()
+    }
+
+    #[test]
+    fn chunk_boundaries_do_not_change_the_tokens() {
+        let memory: &[u8] = b"xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?\
+            mul(8,5))set(a,12)add(a,7)sub(b,a)jmp(1)mul(1234,5)mul(123,4)doif(a)\
+            dontif(b)dondon't()mul(123,456)add(123,4567)do()mul(7,8";
+        for instructions in [PART1, PART2, FULL] {
+            let whole = tokens(BufReader::with_capacity(CHUNK, memory), instructions);
+            assert!(!whole.is_empty());
+            for chunk in 1..=13 {
+                let chunked = tokens(BufReader::with_capacity(chunk, memory), instructions);
+                assert_eq!(chunked, whole, "chunk {}", chunk);
+            }
+        }
+    }
 }
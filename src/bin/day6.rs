@@ -1,9 +1,35 @@
+use std::fmt::Debug;
 use std::fs::File;
+use std::hash::Hash;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::str::FromStr; // Added to make `from_str()` work with `strum::EnumString`
 use strum::{Display, EnumString, IntoStaticStr};
 
+/// A cell on the map as (layer, row, col). Flat maps only have layer 0.
+type Position = (i32, i32, i32);
+
+/// The map layers, each a grid of characters. Flat maps have a single layer.
+type Layers = Vec<Vec<Vec<char>>>;
+
+/// Describes how a guard can face and move over a map.
+trait Topology {
+    type Heading: Copy + Eq + Hash + Debug + FromStr + std::fmt::Display + 'static;
+
+    /// Every heading, in clockwise rotation order.
+    const HEADINGS: &'static [Self::Heading];
+
+    /// The heading after turning right.
+    fn rotate(heading: Self::Heading) -> Self::Heading;
+
+    /// The cell reached by taking one step from `pos` facing `heading`.
+    fn front(pos: Position, heading: Self::Heading) -> Position;
+
+    fn heading_index(heading: Self::Heading) -> usize {
+        Self::HEADINGS.iter().position(|&h| h == heading).unwrap()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoStaticStr, Display, EnumString)]
 enum Direction {
     #[strum(serialize = "^")]
@@ -16,42 +42,190 @@ enum Direction {
     Left,
 }
 
+/// The puzzle's square grid with four headings.
+#[derive(Debug, Clone, Copy)]
+struct Square;
+
+impl Topology for Square {
+    type Heading = Direction;
+
+    const HEADINGS: &'static [Direction] = &[
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+
+    fn rotate(heading: Direction) -> Direction {
+        match heading {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+
+    fn front((layer, row, col): Position, heading: Direction) -> Position {
+        match heading {
+            Direction::Up => (layer, row - 1, col),
+            Direction::Right => (layer, row, col + 1),
+            Direction::Down => (layer, row + 1, col),
+            Direction::Left => (layer, row, col - 1),
+        }
+    }
+}
+
+/// Hex headings, written with the keys around `s` on a keyboard
+/// (`w` `e` / `a` `d` / `z` `x`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoStaticStr, Display, EnumString)]
+enum HexDirection {
+    #[strum(serialize = "e")]
+    NorthEast,
+    #[strum(serialize = "d")]
+    East,
+    #[strum(serialize = "x")]
+    SouthEast,
+    #[strum(serialize = "z")]
+    SouthWest,
+    #[strum(serialize = "a")]
+    West,
+    #[strum(serialize = "w")]
+    NorthWest,
+}
+
+/// A hex grid stored in "odd-r" offset layout: every odd row is shifted half
+/// a cell to the right. The guard turns by 60° at a time.
+#[derive(Debug, Clone, Copy)]
+struct Hex;
+
+impl Topology for Hex {
+    type Heading = HexDirection;
+
+    const HEADINGS: &'static [HexDirection] = &[
+        HexDirection::NorthEast,
+        HexDirection::East,
+        HexDirection::SouthEast,
+        HexDirection::SouthWest,
+        HexDirection::West,
+        HexDirection::NorthWest,
+    ];
+
+    fn rotate(heading: HexDirection) -> HexDirection {
+        match heading {
+            HexDirection::NorthEast => HexDirection::East,
+            HexDirection::East => HexDirection::SouthEast,
+            HexDirection::SouthEast => HexDirection::SouthWest,
+            HexDirection::SouthWest => HexDirection::West,
+            HexDirection::West => HexDirection::NorthWest,
+            HexDirection::NorthWest => HexDirection::NorthEast,
+        }
+    }
+
+    fn front((layer, row, col): Position, heading: HexDirection) -> Position {
+        // Diagonal neighbours depend on whether the row is shifted
+        let shift = row.rem_euclid(2);
+        match heading {
+            HexDirection::NorthEast => (layer, row - 1, col + shift),
+            HexDirection::East => (layer, row, col + 1),
+            HexDirection::SouthEast => (layer, row + 1, col + shift),
+            HexDirection::SouthWest => (layer, row + 1, col + shift - 1),
+            HexDirection::West => (layer, row, col - 1),
+            HexDirection::NorthWest => (layer, row - 1, col + shift - 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoStaticStr, Display, EnumString)]
+enum VoxelDirection {
+    #[strum(serialize = "^")]
+    North,
+    #[strum(serialize = ">")]
+    East,
+    #[strum(serialize = "v")]
+    South,
+    #[strum(serialize = "<")]
+    West,
+    #[strum(serialize = "+")]
+    Above,
+    #[strum(serialize = "-")]
+    Below,
+}
+
+/// Stacked square layers. After the four compass turns the guard turns to
+/// face the layer above, then the layer below, then north again.
+#[derive(Debug, Clone, Copy)]
+struct Voxel;
+
+impl Topology for Voxel {
+    type Heading = VoxelDirection;
+
+    const HEADINGS: &'static [VoxelDirection] = &[
+        VoxelDirection::North,
+        VoxelDirection::East,
+        VoxelDirection::South,
+        VoxelDirection::West,
+        VoxelDirection::Above,
+        VoxelDirection::Below,
+    ];
+
+    fn rotate(heading: VoxelDirection) -> VoxelDirection {
+        match heading {
+            VoxelDirection::North => VoxelDirection::East,
+            VoxelDirection::East => VoxelDirection::South,
+            VoxelDirection::South => VoxelDirection::West,
+            VoxelDirection::West => VoxelDirection::Above,
+            VoxelDirection::Above => VoxelDirection::Below,
+            VoxelDirection::Below => VoxelDirection::North,
+        }
+    }
+
+    fn front((layer, row, col): Position, heading: VoxelDirection) -> Position {
+        match heading {
+            VoxelDirection::North => (layer, row - 1, col),
+            VoxelDirection::East => (layer, row, col + 1),
+            VoxelDirection::South => (layer, row + 1, col),
+            VoxelDirection::West => (layer, row, col - 1),
+            VoxelDirection::Above => (layer + 1, row, col),
+            VoxelDirection::Below => (layer - 1, row, col),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-struct Guard {
-    direction: Direction,
+struct Guard<T: Topology> {
+    direction: T::Heading,
+    layer: i32,
     row: i32,
     col: i32,
 }
 
-impl Guard {
-    fn new(direction: Direction, row: i32, col: i32) -> Self {
+impl<T: Topology> Guard<T> {
+    fn new(direction: T::Heading, (layer, row, col): Position) -> Self {
         Self {
             direction,
+            layer,
             row,
             col,
         }
     }
 
-    fn from_char(c: char, row: usize, col: usize) -> Option<Self> {
-        Direction::from_str(&c.to_string())
+    fn from_char(c: char, layer: usize, row: usize, col: usize) -> Option<Self> {
+        T::Heading::from_str(&c.to_string())
             .ok()
-            .map(|d| Self::new(d, row as i32, col as i32))
+            .map(|d| Self::new(d, (layer as i32, row as i32, col as i32)))
+    }
+
+    fn position(&self) -> Position {
+        (self.layer, self.row, self.col)
     }
 
     fn rotate(&mut self) {
-        self.direction = match self.direction {
-            Direction::Up => Direction::Right,
-            Direction::Right => Direction::Down,
-            Direction::Down => Direction::Left,
-            Direction::Left => Direction::Up,
-        };
+        self.direction = T::rotate(self.direction);
     }
 
     fn walk(&mut self) {
         // Move to its front position
-        let (new_row, new_col) = self.front_position();
-        self.row = new_row;
-        self.col = new_col;
+        (self.layer, self.row, self.col) = self.front_position();
     }
 
     #[allow(dead_code)]
@@ -59,56 +233,47 @@ impl Guard {
         self.direction.to_string().chars().next().unwrap()
     }
 
-    fn front_position(&self) -> (i32, i32) {
+    fn front_position(&self) -> Position {
         // Calculate the position in front of the guard based on its direction
-        match self.direction {
-            Direction::Up => (self.row - 1, self.col),
-            Direction::Right => (self.row, self.col + 1),
-            Direction::Down => (self.row + 1, self.col),
-            Direction::Left => (self.row, self.col - 1),
-        }
+        T::front(self.position(), self.direction)
     }
 }
 
-struct Map {
-    grid: Vec<Vec<char>>,
-    guard: Guard,
+struct Map<T: Topology> {
+    grid: Layers,
+    guard: Guard<T>,
 }
 
-impl Map {
+impl<T: Topology + Clone> Map<T> {
     /// Places an obstacle at the given grid position.
     /// If the position is out of bounds, does nothing.
-    fn place_obstacle(&mut self, row: usize, col: usize) {
-        if row < self.grid.len() && col < self.grid[row].len() {
-            self.grid[row][col] = '#';
+    fn place_obstacle(&mut self, layer: usize, row: usize, col: usize) {
+        if let Some(cell) = self.cell_mut(layer, row, col) {
+            *cell = '#';
         }
     }
 
     /// Removes an obstacle at the given grid position, replacing it with a space.
     /// If the position is out of bounds or not an obstacle, does nothing.
-    fn remove_obstacle(&mut self, row: usize, col: usize) {
-        if row < self.grid.len() && col < self.grid[row].len() {
-            if self.grid[row][col] == '#' {
-                self.grid[row][col] = ' ';
-            }
+    fn remove_obstacle(&mut self, layer: usize, row: usize, col: usize) {
+        if let Some(cell) = self.cell_mut(layer, row, col).filter(|c| **c == '#') {
+            *cell = ' ';
         }
     }
 
-    fn new(grid: Vec<Vec<char>>) -> Self {
-        // Find the guard in the grid
-        let mut guard = None;
+    fn cell_mut(&mut self, layer: usize, row: usize, col: usize) -> Option<&mut char> {
+        self.grid.get_mut(layer)?.get_mut(row)?.get_mut(col)
+    }
 
-        for (row_idx, row) in grid.iter().enumerate() {
-            for (col_idx, &cell) in row.iter().enumerate() {
-                if let Some(g) = Guard::from_char(cell, row_idx, col_idx) {
-                    guard = Some(g);
-                    break;
-                }
-            }
-            if guard.is_some() {
-                break;
-            }
-        }
+    fn new(grid: Layers) -> Self {
+        // Find the guard in the grid
+        let guard = grid.iter().enumerate().find_map(|(layer_idx, layer)| {
+            layer.iter().enumerate().find_map(|(row_idx, row)| {
+                row.iter()
+                    .enumerate()
+                    .find_map(|(col_idx, &cell)| Guard::from_char(cell, layer_idx, row_idx, col_idx))
+            })
+        });
 
         Self {
             grid,
@@ -116,65 +281,216 @@ impl Map {
         }
     }
 
-    fn is_obstacle(&self, row: i32, col: i32) -> bool {
-        if row >= 0 && col >= 0 {
-            let row = row as usize;
-            let col = col as usize;
-            if row < self.grid.len() && col < self.grid[row].len() {
-                self.grid[row][col] == '#'
-            } else {
-                false
-            }
+    fn cell(&self, (layer, row, col): Position) -> Option<char> {
+        if layer >= 0 && row >= 0 && col >= 0 {
+            self.grid
+                .get(layer as usize)?
+                .get(row as usize)?
+                .get(col as usize)
+                .copied()
         } else {
-            false
+            None
         }
     }
 
-    fn is_within_map(&self, row: i32, col: i32) -> bool {
-        if row >= 0 && col >= 0 {
-            let row = row as usize;
-            let col = col as usize;
-            row < self.grid.len() && col < self.grid[row].len()
+    fn is_obstacle(&self, pos: Position) -> bool {
+        self.cell(pos) == Some('#')
+    }
+
+    fn is_within_map(&self, pos: Position) -> bool {
+        self.cell(pos).is_some()
+    }
+
+    fn get_guard(&self) -> Guard<T> {
+        self.guard.clone()
+    }
+
+    /// Advances the guard by one move (a turn or a step).
+    /// Returns `None` if the move takes the guard off the map.
+    fn step(&self, guard: &Guard<T>) -> Option<Guard<T>> {
+        let mut next = guard.clone();
+
+        if self.is_obstacle(next.front_position()) {
+            next.rotate();
         } else {
-            false
+            next.walk();
         }
+
+        self.is_within_map(next.position()).then_some(next)
     }
+}
 
-    fn get_guard(&self) -> Guard {
-        self.guard.clone()
+/// Where a guard starting from a given pose ends up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fate<H> {
+    /// The guard walks off the map from this cell and heading.
+    Exit { pos: Position, direction: H },
+    /// The guard ends up circling the cycle with this index.
+    Loop(usize),
+}
+
+/// The fate of every starting pose on the map, computed in one pass over the
+/// successor graph. Each pose has exactly one successor, so every walk either
+/// leaves the map or falls into a cycle.
+struct StateSpace<T: Topology> {
+    nrows: usize,
+    ncols: usize,
+    fates: Vec<Option<Fate<T::Heading>>>,
+    cycles: Vec<Vec<(Position, T::Heading)>>,
+}
+
+impl<T: Topology + Clone> StateSpace<T> {
+    fn analyse(map: &Map<T>) -> Self {
+        let nrows = map.grid.iter().map(Vec::len).max().unwrap_or(0);
+        let ncols = map.grid.iter().flatten().map(Vec::len).max().unwrap_or(0);
+        let nstates = map.grid.len() * nrows * ncols * T::HEADINGS.len();
+
+        let mut space = Self {
+            nrows,
+            ncols,
+            fates: vec![None; nstates],
+            cycles: Vec::new(),
+        };
+        // Position of a state on the walk currently being explored, if any
+        let mut on_path = vec![None; nstates];
+
+        for (layer, rows) in map.grid.iter().enumerate() {
+            for (row, cells) in rows.iter().enumerate() {
+                for (col, &cell) in cells.iter().enumerate() {
+                    if cell == '#' {
+                        continue;
+                    }
+
+                    for &direction in T::HEADINGS {
+                        let start = Guard::new(direction, (layer as i32, row as i32, col as i32));
+                        if space.fates[space.index(&start)].is_some() {
+                            continue;
+                        }
+
+                        // Follow successors until we reach a known fate, leave the
+                        // map, or revisit a state of this walk (a new cycle)
+                        let mut path: Vec<Guard<T>> = Vec::new();
+                        let mut current = start;
+                        let fate = loop {
+                            let i = space.index(&current);
+                            if let Some(fate) = space.fates[i] {
+                                break fate;
+                            }
+                            if let Some(cycle_start) = on_path[i] {
+                                let id = space.cycles.len();
+                                space.cycles.push(
+                                    path[cycle_start..]
+                                        .iter()
+                                        .map(|g| (g.position(), g.direction))
+                                        .collect(),
+                                );
+                                break Fate::Loop(id);
+                            }
+
+                            on_path[i] = Some(path.len());
+                            path.push(current.clone());
+                            match map.step(&current) {
+                                Some(next) => current = next,
+                                None => {
+                                    break Fate::Exit {
+                                        pos: current.position(),
+                                        direction: current.direction,
+                                    };
+                                }
+                            }
+                        };
+
+                        for g in &path {
+                            let i = space.index(g);
+                            on_path[i] = None;
+                            space.fates[i] = Some(fate);
+                        }
+                    }
+                }
+            }
+        }
+
+        space
+    }
+
+    fn index(&self, guard: &Guard<T>) -> usize {
+        let cell = (guard.layer as usize * self.nrows + guard.row as usize) * self.ncols
+            + guard.col as usize;
+        cell * T::HEADINGS.len() + T::heading_index(guard.direction)
+    }
+
+    fn fate(&self, guard: &Guard<T>) -> Option<Fate<T::Heading>> {
+        self.fates[self.index(guard)]
+    }
+
+    /// Renders the map with each open cell showing how many of its starting
+    /// headings end in a loop (`.` when none do). Layers are separated by a
+    /// blank line.
+    fn heatmap(&self, map: &Map<T>) -> String {
+        let mut out = String::new();
+        for (layer, rows) in map.grid.iter().enumerate() {
+            if layer > 0 {
+                out.push('\n');
+            }
+            for (row, cells) in rows.iter().enumerate() {
+                for (col, &cell) in cells.iter().enumerate() {
+                    if cell == '#' {
+                        out.push('#');
+                        continue;
+                    }
+                    let pos = (layer as i32, row as i32, col as i32);
+                    let looping = T::HEADINGS
+                        .iter()
+                        .filter(|&&d| matches!(self.fate(&Guard::new(d, pos)), Some(Fate::Loop(_))))
+                        .count();
+                    out.push(if looping == 0 {
+                        '.'
+                    } else {
+                        char::from_digit(looping as u32, 10).unwrap()
+                    });
+                }
+                out.push('\n');
+            }
+        }
+        out
     }
 }
 
-fn read_input() -> Vec<Vec<char>> {
+/// Reads the map, splitting it into layers on blank lines.
+fn read_input() -> Layers {
     let path = Path::new("input/day6.txt");
 
     // Open the file
-    let file = File::open(&path).expect("Failed to open file");
+    let file = File::open(path).expect("Failed to open file");
     let reader = BufReader::new(file);
 
     // Read the file line by line and convert to array of arrays of characters
-    reader
-        .lines()
-        .filter_map(Result::ok)
-        .map(|line| line.chars().collect())
-        .collect()
+    let mut layers = vec![Vec::new()];
+    for line in reader.lines() {
+        let line = line.expect("Failed to read line");
+        if line.trim().is_empty() {
+            layers.push(Vec::new());
+        } else {
+            layers.last_mut().unwrap().push(line.chars().collect());
+        }
+    }
+    layers.retain(|layer| !layer.is_empty());
+    layers
 }
 
-fn part1(input: &[Vec<char>]) -> usize {
-    let map = Map::new(input.to_vec());
+fn part1<T: Topology + Clone>(input: &Layers) -> usize {
+    let map = Map::<T>::new(input.to_vec());
     let mut guard = map.get_guard();
 
     // Track visited positions
-    let mut visited = std::collections::HashSet::<(i32, i32)>::new();
+    let mut visited = std::collections::HashSet::<Position>::new();
 
     // Continue moving the guard until it leaves the map or we detect a loop
-    while map.is_within_map(guard.row, guard.col) {
-        visited.insert((guard.row, guard.col));
+    while map.is_within_map(guard.position()) {
+        visited.insert(guard.position());
 
         // Try to move forward
-        let (new_row, new_col) = guard.front_position();
-
-        if map.is_obstacle(new_row, new_col) {
+        if map.is_obstacle(guard.front_position()) {
             // Obstacle ahead, rotate and don't move
             guard.rotate();
         } else {
@@ -186,57 +502,120 @@ fn part1(input: &[Vec<char>]) -> usize {
     visited.len()
 }
 
-fn part2(input: &[Vec<char>]) -> usize {
-    let mut map = Map::new(input.to_vec());
+fn part2<T: Topology + Clone>(input: &Layers) -> usize {
+    let mut map = Map::<T>::new(input.to_vec());
     let mut count = 0;
-    let guard_start_pos = (map.guard.row as usize, map.guard.col as usize);
+    let guard_start_pos = map.guard.position();
+
+    for layer in 0..map.grid.len() {
+        for row in 0..map.grid[layer].len() {
+            for col in 0..map.grid[layer][row].len() {
+                // Skip guard start position and existing obstacles
+                if (layer as i32, row as i32, col as i32) == guard_start_pos
+                    || map.grid[layer][row][col] == '#'
+                {
+                    continue;
+                }
 
-    for row in 0..map.grid.len() {
-        for col in 0..map.grid[row].len() {
-            // Skip guard start position and existing obstacles
-            if (row, col) == guard_start_pos || map.grid[row][col] == '#' {
-                continue;
-            }
+                map.place_obstacle(layer, row, col);
+
+                let mut sim_guard = map.get_guard();
+                let mut visited_states = std::collections::HashSet::new();
+
+                while map.is_within_map(sim_guard.position()) {
+                    let state = (sim_guard.position(), sim_guard.direction);
+
+                    // Detect loop if we've seen this exact state before
+                    if visited_states.contains(&state) {
+                        break;
+                    }
+                    visited_states.insert(state);
 
-            map.place_obstacle(row, col);
-            
-            let mut sim_guard = map.get_guard();
-            let mut visited_states = std::collections::HashSet::new();
-
-            while map.is_within_map(sim_guard.row, sim_guard.col) {
-                let state = (sim_guard.row, sim_guard.col, sim_guard.direction);
-                
-                // Detect loop if we've seen this exact state before
-                if visited_states.contains(&state) {
-                    break;
+                    if map.is_obstacle(sim_guard.front_position()) {
+                        sim_guard.rotate();
+                    } else {
+                        sim_guard.walk();
+                    }
                 }
-                visited_states.insert(state);
 
-                let (new_row, new_col) = sim_guard.front_position();
-                if map.is_obstacle(new_row, new_col) {
-                    sim_guard.rotate();
-                } else {
-                    sim_guard.walk();
+                // Count if guard is still in map (loop detected) or exited
+                if map.is_within_map(sim_guard.position()) {
+                    count += 1;
                 }
-            }
 
-            // Count if guard is still in map (loop detected) or exited
-            if map.is_within_map(sim_guard.row, sim_guard.col) {
-                count += 1;
+                map.remove_obstacle(layer, row, col);
             }
-
-            map.remove_obstacle(row, col);
         }
     }
 
     count
 }
 
-fn main() {
-    let input = read_input();
+fn analyse<T: Topology + Clone>(input: &Layers, heatmap_path: Option<&str>) {
+    let map = Map::<T>::new(input.to_vec());
+    let space = StateSpace::analyse(&map);
+
+    let (exiting, looping) = space
+        .fates
+        .iter()
+        .flatten()
+        .fold((0, 0), |(e, l), fate| match fate {
+            Fate::Exit { .. } => (e + 1, l),
+            Fate::Loop(_) => (e, l + 1),
+        });
+    println!("Starting poses: {} exit, {} loop", exiting, looping);
+    println!("Distinct cycles: {}", space.cycles.len());
+
+    match space.fate(&map.get_guard()) {
+        Some(Fate::Exit { pos, direction }) => {
+            println!("Guard exits from {:?} heading {:?}", pos, direction)
+        }
+        Some(Fate::Loop(id)) => println!(
+            "Guard loops in cycle {} of length {}",
+            id,
+            space.cycles[id].len()
+        ),
+        None => {}
+    }
+
+    let heatmap = space.heatmap(&map);
+    match heatmap_path {
+        Some(path) => {
+            std::fs::write(path, heatmap).expect("Failed to write heatmap");
+            println!("Heatmap written to {}", path);
+        }
+        None => print!("{}", heatmap),
+    }
+}
 
-    let result = part1(&input);
+fn run<T: Topology + Clone>(input: &Layers, args: &[String]) {
+    let result = part1::<T>(input);
     println!("Part 1 result: {}", result);
-    let result2 = part2(&input);
+    let result2 = part2::<T>(input);
     println!("Part 2 result: {}", result2);
+
+    // `--analyse [path]` classifies every starting pose and exports a heatmap
+    if let Some(pos) = args.iter().position(|a| a == "--analyse") {
+        let path = args.get(pos + 1).filter(|a| !a.starts_with("--"));
+        analyse::<T>(input, path.map(String::as_str));
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let input = read_input();
+
+    // `--topology square|hex|voxel` picks how the guard moves over the map
+    let topology = args
+        .iter()
+        .position(|a| a == "--topology")
+        .and_then(|pos| args.get(pos + 1))
+        .map_or("square", String::as_str);
+
+    match topology {
+        "square" => run::<Square>(&input, &args),
+        "hex" => run::<Hex>(&input, &args),
+        "voxel" => run::<Voxel>(&input, &args),
+        other => panic!("Unknown topology: {}", other),
+    }
 }
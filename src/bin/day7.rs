@@ -1,156 +1,1814 @@
+use num_bigint::BigInt;
+use num_integer::Roots;
+use num_traits::{
+    CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, FromPrimitive, Signed, ToPrimitive,
+};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::ops::BitXor;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn read_input() -> Vec<(i64, Vec<i64>)> {
+/// Integer type the solvers work with: `i64`, or `BigInt` for equations
+/// beyond 64 bits. All arithmetic is checked, so an operation that overflows
+/// yields `None` and prunes that branch of the search instead of wrapping or
+/// panicking.
+trait Number:
+    Send
+    + Sync
+    + Clone
+    + Ord
+    + Debug
+    + Display
+    + FromStr
+    + Signed
+    + Roots
+    + ToPrimitive
+    + FromPrimitive
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + CheckedDiv
+    + BitXor<Output = Self>
+{
+}
+
+impl<T> Number for T where
+    T: Send
+        + Sync
+        + Clone
+        + Ord
+        + Debug
+        + Display
+        + FromStr
+        + Signed
+        + Roots
+        + ToPrimitive
+        + FromPrimitive
+        + CheckedAdd
+        + CheckedSub
+        + CheckedMul
+        + CheckedDiv
+        + BitXor<Output = Self>
+{
+}
+
+/// Largest exponent `pow` accepts for bases other than 0 and ±1. Anything
+/// bigger overflows `i64` anyway and would only build enormous big integers.
+const MAX_EXPONENT: u32 = 1024;
+
+/// A binary operator that can be placed between two operands of a
+/// calibration equation. The order they are applied in depends on the
+/// [`Evaluation`] mode; the puzzle's is strictly left to right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Mul,
+    Concat,
+    Sub,
+    DivExact,
+    Xor,
+    Pow,
+    Max,
+}
+
+impl Operator {
+    /// Every operator the solver knows about.
+    const ALL: [Operator; 8] = [
+        Operator::Add,
+        Operator::Mul,
+        Operator::Concat,
+        Operator::Sub,
+        Operator::DivExact,
+        Operator::Xor,
+        Operator::Pow,
+        Operator::Max,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Operator::Add => "add",
+            Operator::Mul => "mul",
+            Operator::Concat => "concat",
+            Operator::Sub => "sub",
+            Operator::DivExact => "div",
+            Operator::Xor => "xor",
+            Operator::Pow => "pow",
+            Operator::Max => "max",
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Mul => "*",
+            Operator::Concat => "||",
+            Operator::Sub => "-",
+            Operator::DivExact => "/",
+            Operator::Xor => "^",
+            Operator::Pow => "**",
+            Operator::Max => "max",
+        }
+    }
+
+    /// Binding strength under [`Evaluation::Precedence`]; higher binds
+    /// tighter. Operators of equal strength associate to the left.
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Concat => 5,
+            Operator::Pow => 4,
+            Operator::Mul | Operator::DivExact => 3,
+            Operator::Add | Operator::Sub => 2,
+            Operator::Xor => 1,
+            Operator::Max => 0,
+        }
+    }
+
+    /// Whether the result is never smaller than either operand when both are
+    /// positive, so a search can drop values that have passed its target.
+    fn is_monotone(self) -> bool {
+        matches!(
+            self,
+            Operator::Add | Operator::Mul | Operator::Concat | Operator::Max
+        )
+    }
+
+    /// Applies the operator, or returns `None` if it is undefined for these
+    /// operands (e.g. a division with a remainder) or the result overflows.
+    fn apply<N: Number>(self, a: &N, b: &N) -> Option<N> {
+        match self {
+            Operator::Add => a.checked_add(b),
+            Operator::Mul => a.checked_mul(b),
+            Operator::Concat => concat(a, b),
+            Operator::Sub => a.checked_sub(b),
+            Operator::DivExact => exact_div(a, b),
+            Operator::Xor => Some(a.clone() ^ b.clone()),
+            Operator::Pow => checked_pow(a, b.to_u32()?),
+            Operator::Max => Some(a.max(b).clone()),
+        }
+    }
+
+    /// Finds the left operands `a` for which `a op b == result`. Candidates
+    /// that overflow are dropped, as `apply` could never produce them.
+    fn unapply<N: Number>(self, result: &N, b: &N) -> Inverse<N> {
+        match self {
+            Operator::Add => result.checked_sub(b).into(),
+            Operator::Mul => {
+                if b.is_zero() {
+                    if result.is_zero() {
+                        Inverse::Ambiguous
+                    } else {
+                        Inverse::None
+                    }
+                } else {
+                    exact_div(result, b).into()
+                }
+            }
+            Operator::Concat => split_concat(result, b).into(),
+            Operator::Sub => result.checked_add(b).into(),
+            Operator::DivExact => {
+                if b.is_zero() {
+                    Inverse::None
+                } else {
+                    result.checked_mul(b).into()
+                }
+            }
+            Operator::Xor => Inverse::One(result.clone() ^ b.clone()),
+            Operator::Pow => match b.to_u32() {
+                None => Inverse::None,
+                Some(0) => {
+                    if result.is_one() {
+                        Inverse::Ambiguous
+                    } else {
+                        Inverse::None
+                    }
+                }
+                Some(1) => Inverse::One(result.clone()),
+                Some(exp) => match integer_root(result, exp) {
+                    Some(root) if root.abs() > N::one() && exp > MAX_EXPONENT => Inverse::None,
+                    // Even powers lose the sign of the base
+                    Some(root) if exp % 2 == 0 && !root.is_zero() => {
+                        Inverse::Two(root.clone(), -root)
+                    }
+                    Some(root) => Inverse::One(root),
+                    None => Inverse::None,
+                },
+            },
+            Operator::Max => {
+                if b > result {
+                    Inverse::None
+                } else if b < result {
+                    Inverse::One(result.clone())
+                } else {
+                    // Any left operand up to `result` works
+                    Inverse::Ambiguous
+                }
+            }
+        }
+    }
+}
+
+/// The left operands that undo an operator, see [`Operator::unapply`].
+enum Inverse<N> {
+    None,
+    One(N),
+    Two(N, N),
+    /// Too many candidates to list, so the caller has to search forwards.
+    Ambiguous,
+}
+
+impl<N> From<Option<N>> for Inverse<N> {
+    fn from(value: Option<N>) -> Self {
+        value.map_or(Inverse::None, Inverse::One)
+    }
+}
+
+/// The power of ten that shifts a number far enough left to append the
+/// digits of `b`, i.e. `10^digits(b)`. `None` if it does not fit.
+fn concat_shift<N: Number>(b: &N) -> Option<N> {
+    let ten = N::from_u8(10)?;
+    let mut shift = ten.clone();
+    while shift <= *b {
+        shift = shift.checked_mul(&ten)?;
+    }
+    Some(shift)
+}
+
+/// Appends the digits of `b` to `a`, so `12 || 345 == 12345`, using only
+/// arithmetic. Both operands must be non-negative.
+fn concat<N: Number>(a: &N, b: &N) -> Option<N> {
+    if a.is_negative() || b.is_negative() {
+        return None;
+    }
+    // `0 || b` is just `b`, even when shifting `b` would overflow
+    if a.is_zero() {
+        return Some(b.clone());
+    }
+    a.checked_mul(&concat_shift(b)?)?.checked_add(b)
+}
+
+/// Undoes [`concat`]: finds the `a` with `a || b == result` by stripping the
+/// digits of `b` off the end of `result`.
+fn split_concat<N: Number>(result: &N, b: &N) -> Option<N> {
+    if result.is_negative() || b.is_negative() || result < b {
+        return None;
+    }
+    if result == b {
+        return Some(N::zero());
+    }
+    exact_div(&result.checked_sub(b)?, &concat_shift(b)?)
+}
+
+/// Divides `a` by `b` if the division leaves no remainder.
+fn exact_div<N: Number>(a: &N, b: &N) -> Option<N> {
+    let quotient = a.checked_div(b)?;
+    (quotient.checked_mul(b).as_ref() == Some(a)).then_some(quotient)
+}
+
+/// Raises `base` to `exp`, or returns `None` on overflow or when the exponent
+/// is too large to be worth computing.
+fn checked_pow<N: Number>(base: &N, exp: u32) -> Option<N> {
+    if base.abs() > N::one() && exp > MAX_EXPONENT {
+        return None;
+    }
+    num_traits::checked_pow(base.clone(), exp as usize)
+}
+
+/// Returns the non-negative `r` with `r^exp == value` (or the negative one for
+/// odd `exp` and negative `value`), if there is such an integer.
+fn integer_root<N: Number>(value: &N, exp: u32) -> Option<N> {
+    if value.is_negative() {
+        return if exp % 2 == 1 {
+            integer_root(&N::zero().checked_sub(value)?, exp).map(|r| -r)
+        } else {
+            None
+        };
+    }
+
+    let root = value.nth_root(exp);
+    (checked_pow(&root, exp).as_ref() == Some(value)).then_some(root)
+}
+
+impl FromStr for Operator {
+    type Err = String;
+
+    /// Parses an operator from either its name (`concat`) or symbol (`||`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Operator::ALL
+            .into_iter()
+            .find(|op| op.name() == s || op.symbol() == s)
+            .ok_or_else(|| format!("Unknown operator: {}", s))
+    }
+}
+
+/// Parses a comma separated operator list such as `add,mul,concat`.
+fn parse_operators(list: &str) -> Result<Vec<Operator>, String> {
+    list.split(',').map(Operator::from_str).collect()
+}
+
+/// A calibration equation as `(line, target, numbers)`, with the 1-based
+/// line of the input file it was read from.
+type Equation<N> = (usize, N, Vec<N>);
+
+/// Reads the equations, skipping (and reporting) lines with numbers that
+/// are malformed or do not fit in `N`.
+fn read_input<N: Number>() -> Vec<Equation<N>> {
     let path = Path::new("input/day7.txt");
 
     // Open the file
-    let file = File::open(&path).expect("Failed to open file");
+    let file = File::open(path).expect("Failed to open file");
     let reader = BufReader::new(file);
 
     let mut result = Vec::new();
-    for line in reader.lines() {
+    for (line_idx, line) in reader.lines().enumerate() {
         let line = line.expect("Failed to read line");
         let parts: Vec<&str> = line.split(": ").collect();
         if parts.len() != 2 {
             continue;
         }
 
-        let first_part = parts[0].parse::<i64>().ok();
-        let second_part: Vec<i64> = parts[1]
+        let first_part = parts[0].parse::<N>();
+        let second_part: Result<Vec<N>, _> = parts[1]
             .split_whitespace()
-            .filter_map(|s| s.parse::<i64>().ok())
+            .map(|s| s.parse::<N>())
             .collect();
 
-        if let Some(first) = first_part {
-            result.push((first, second_part));
+        match (first_part, second_part) {
+            (Ok(first), Ok(numbers)) => result.push((line_idx + 1, first, numbers)),
+            _ => eprintln!(
+                "Skipping line {}: invalid or out of range number (try --bigint)",
+                line_idx + 1
+            ),
         }
     }
 
     result
 }
 
-fn part1(input: &Vec<(i64, Vec<i64>)>) -> i64 {
-    let mut count: i64 = 0;
+/// How to search for an operator assignment that satisfies an equation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Solver {
+    /// Evaluates every assignment from scratch. Kept as a reference.
+    BruteForce,
+    /// Undoes operators from the target backwards, pruning dead ends early.
+    Backward,
+}
 
-    for (first, numbers) in input {
-        let m = numbers.len();
-        if m == 0 {
-            continue;
+impl FromStr for Solver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "brute" => Ok(Solver::BruteForce),
+            "backward" => Ok(Solver::Backward),
+            _ => Err(format!("Unknown solver: {}", s)),
         }
+    }
+}
+
+/// The order in which the operators of an equation are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Evaluation {
+    /// Strictly left to right, as in the puzzle.
+    LeftToRight,
+    /// Strictly right to left.
+    RightToLeft,
+    /// By [`Operator::precedence`], so `*` goes before `+`.
+    Precedence,
+    /// Any placement of brackets the solver likes.
+    AnyParens,
+}
+
+impl FromStr for Evaluation {
+    type Err = String;
 
-        if m == 1 {
-            if numbers[0] == *first {
-                count += 1;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ltr" => Ok(Evaluation::LeftToRight),
+            "rtl" => Ok(Evaluation::RightToLeft),
+            "precedence" => Ok(Evaluation::Precedence),
+            "parens" => Ok(Evaluation::AnyParens),
+            _ => Err(format!("Unknown evaluation mode: {}", s)),
+        }
+    }
+}
+
+impl Evaluation {
+    /// Checks whether the numbers joined by `assignment` evaluate to `target`.
+    fn reaches<N: Number>(self, target: &N, numbers: &[N], assignment: &[Operator]) -> bool {
+        match self {
+            Evaluation::LeftToRight => {
+                let mut result = Some(numbers[0].clone());
+                for (op, next) in assignment.iter().zip(&numbers[1..]) {
+                    result = result.and_then(|r| op.apply(&r, next));
+                }
+                result.as_ref() == Some(target)
             }
-            continue;
+            Evaluation::RightToLeft => {
+                let (last, rest) = numbers.split_last().unwrap();
+                let mut result = Some(last.clone());
+                for (op, prev) in assignment.iter().zip(rest).rev() {
+                    result = result.and_then(|r| op.apply(prev, &r));
+                }
+                result.as_ref() == Some(target)
+            }
+            Evaluation::Precedence => {
+                evaluate_by_precedence(numbers, assignment).as_ref() == Some(target)
+            }
+            Evaluation::AnyParens => bracket(target, numbers, assignment).is_some(),
         }
+    }
 
-        let mut valid = false;
-        let num_ops = m - 1;
+    /// Writes out a satisfying equation such as `190 = 10 * 19`. With
+    /// [`Evaluation::AnyParens`] the brackets that make it work are included,
+    /// and right-to-left chains nest to the right, e.g. `3 = 2 - (3 - 4)`.
+    fn format<N: Number>(self, target: &N, numbers: &[N], assignment: &[Operator]) -> String {
+        if self == Evaluation::AnyParens
+            && let Some(expr) = bracket(target, numbers, assignment)
+        {
+            return format!("{} = {}", target, expr);
+        }
 
-        for mask in 0..(1 << num_ops) {
-            let mut ops = Vec::new();
-            for i in 0..num_ops {
-                if mask & (1 << i) != 0 {
-                    ops.push('*');
+        if self == Evaluation::RightToLeft {
+            let (last, rest) = numbers.split_last().unwrap();
+            let mut expr = last.to_string();
+            for (i, (op, prev)) in assignment.iter().zip(rest).rev().enumerate() {
+                expr = if i == 0 {
+                    format!("{} {} {}", prev, op.symbol(), expr)
                 } else {
-                    ops.push('+');
+                    format!("{} {} ({})", prev, op.symbol(), expr)
+                };
+            }
+            return format!("{} = {}", target, expr);
+        }
+
+        let mut out = format!("{} = {}", target, numbers[0]);
+        for (op, number) in assignment.iter().zip(&numbers[1..]) {
+            out.push_str(&format!(" {} {}", op.symbol(), number));
+        }
+        out
+    }
+}
+
+/// Evaluates with the usual two-stack method, applying pending operators
+/// whenever the next one binds no tighter.
+fn evaluate_by_precedence<N: Number>(numbers: &[N], assignment: &[Operator]) -> Option<N> {
+    fn reduce<N: Number>(values: &mut Vec<N>, pending: &mut Vec<Operator>) -> Option<()> {
+        let op = pending.pop()?;
+        let b = values.pop()?;
+        let a = values.pop()?;
+        values.push(op.apply(&a, &b)?);
+        Some(())
+    }
+
+    let mut values = vec![numbers[0].clone()];
+    let mut pending: Vec<Operator> = Vec::new();
+    for (&op, next) in assignment.iter().zip(&numbers[1..]) {
+        while pending
+            .last()
+            .is_some_and(|top| top.precedence() >= op.precedence())
+        {
+            reduce(&mut values, &mut pending)?;
+        }
+        pending.push(op);
+        values.push(next.clone());
+    }
+    while !pending.is_empty() {
+        reduce(&mut values, &mut pending)?;
+    }
+    values.pop()
+}
+
+/// Finds a bracketing of the numbers joined by `assignment` that evaluates
+/// to `target` and returns it fully bracketed, e.g. `(10 * (2 + 17))`.
+fn bracket<N: Number>(target: &N, numbers: &[N], assignment: &[Operator]) -> Option<String> {
+    let n = numbers.len();
+    // values[i][j] holds every value numbers[i..=j] can take, sorted
+    let mut values: Vec<Vec<Vec<N>>> = vec![vec![Vec::new(); n]; n];
+    for (i, number) in numbers.iter().enumerate() {
+        values[i][i] = vec![number.clone()];
+    }
+    for len in 1..n {
+        for i in 0..n - len {
+            let j = i + len;
+            let mut reachable = Vec::new();
+            for k in i..j {
+                for a in &values[i][k] {
+                    for b in &values[k + 1][j] {
+                        reachable.extend(assignment[k].apply(a, b));
+                    }
                 }
             }
+            reachable.sort();
+            reachable.dedup();
+            values[i][j] = reachable;
+        }
+    }
 
-            let mut result = numbers[0];
-            for i in 0..num_ops {
-                let next = numbers[i + 1];
-                match ops[i] {
-                    '+' => result += next,
-                    '*' => result *= next,
-                    _ => panic!("Invalid operator"),
+    // Walk back down from the full range to recover one bracketing
+    fn build<N: Number>(
+        values: &[Vec<Vec<N>>],
+        numbers: &[N],
+        assignment: &[Operator],
+        i: usize,
+        j: usize,
+        target: &N,
+    ) -> Option<String> {
+        if i == j {
+            return (&numbers[i] == target).then(|| target.to_string());
+        }
+        for k in i..j {
+            for a in &values[i][k] {
+                for b in &values[k + 1][j] {
+                    if assignment[k].apply(a, b).as_ref() == Some(target) {
+                        let left = build(values, numbers, assignment, i, k, a)?;
+                        let right = build(values, numbers, assignment, k + 1, j, b)?;
+                        return Some(format!("({} {} {})", left, assignment[k].symbol(), right));
+                    }
                 }
             }
+        }
+        None
+    }
 
-            if result == *first {
-                valid = true;
+    values[0][n - 1].binary_search(target).ok()?;
+    build(&values, numbers, assignment, 0, n - 1, target)
+}
+
+/// Which operands an equation may use, and in what order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arrangement {
+    /// Every operand, in the given order, as in the puzzle.
+    InOrder,
+    /// Every operand, in any order.
+    Permuted,
+    /// Any non-empty selection of the operands, in any order.
+    Subset,
+}
+
+impl FromStr for Arrangement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in-order" => Ok(Arrangement::InOrder),
+            "permuted" => Ok(Arrangement::Permuted),
+            "subset" => Ok(Arrangement::Subset),
+            _ => Err(format!("Unknown arrangement: {}", s)),
+        }
+    }
+}
+
+/// Most values the subset table may hold before a line is given up on as
+/// having too many operands. How far a line gets depends on its operators
+/// and target more than on its length: pruned by the target, add and mul
+/// stay small for a dozen operands, while concatenation needs gigabytes for
+/// eight.
+const MAX_TABLE_VALUES: usize = 1 << 21;
+
+/// Most operands the subset table is built for at all, since it has an
+/// entry for every subset and any bracketing splits each subset every way.
+const MAX_SUBSET_OPERANDS: usize = 16;
+
+/// Most operands tried in every order when there is no subset recurrence to
+/// share the work between orders.
+const MAX_ENUMERATED_OPERANDS: usize = 7;
+
+/// Everything that decides how equations are solved.
+#[derive(Debug, Clone)]
+struct Config {
+    ops: Vec<Operator>,
+    solver: Solver,
+    evaluation: Evaluation,
+    arrangement: Arrangement,
+    /// Number of lines solved at the same time.
+    threads: usize,
+    /// How long a single line may take before it is given up on.
+    timeout: Option<Duration>,
+    /// When the line currently being solved runs out of time. The searches
+    /// check this as they go and unwind once it has passed.
+    deadline: Option<Instant>,
+}
+
+/// What became of a single line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome<T> {
+    Solved(T),
+    Unsolvable,
+    /// Ran out of time.
+    TimedOut,
+    /// Too many operands to rearrange, see [`MAX_TABLE_VALUES`].
+    TooManyOperands,
+}
+
+impl<T> Outcome<T> {
+    /// The solution, if the line was solved.
+    fn solved(self) -> Option<T> {
+        match self {
+            Outcome::Solved(solution) => Some(solution),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    fn new(ops: &[Operator], solver: Solver) -> Self {
+        Self {
+            ops: ops.to_vec(),
+            solver,
+            evaluation: Evaluation::LeftToRight,
+            arrangement: Arrangement::InOrder,
+            threads: 1,
+            timeout: None,
+            deadline: None,
+        }
+    }
+
+    /// Returns a copy of the config whose deadline starts counting now.
+    fn start_line(&self) -> Config {
+        Config {
+            deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+            ..self.clone()
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Solves one line within the time limit, see [`Config::is_solvable`].
+    fn outcome<N: Number>(&self, target: &N, numbers: &[N]) -> Outcome<()> {
+        let config = self.start_line();
+        let found = match config.arrangement {
+            Arrangement::InOrder => match config.first_solution(target, numbers) {
+                Some(_) => Outcome::Solved(()),
+                None => Outcome::Unsolvable,
+            },
+            _ => match config.rearranged(target, numbers) {
+                Outcome::Solved(_) => Outcome::Solved(()),
+                Outcome::Unsolvable => Outcome::Unsolvable,
+                Outcome::TimedOut => Outcome::TimedOut,
+                Outcome::TooManyOperands => Outcome::TooManyOperands,
+            },
+        };
+        match found {
+            Outcome::Unsolvable if config.expired() => Outcome::TimedOut,
+            found => found,
+        }
+    }
+
+    /// Calls `visit` with each operator assignment that makes the equation
+    /// true, until `visit` returns `true`. Returns whether it stopped early.
+    ///
+    /// The backward solver only understands left-to-right evaluation, so
+    /// other modes always enumerate.
+    fn for_each_solution<N: Number>(
+        &self,
+        target: &N,
+        numbers: &[N],
+        visit: &mut impl FnMut(&[Operator]) -> bool,
+    ) -> bool {
+        match (self.solver, self.evaluation) {
+            (Solver::Backward, Evaluation::LeftToRight) => {
+                !numbers.is_empty() && backward(self, target, numbers, &mut Vec::new(), visit)
+            }
+            _ => brute_force(self, target, numbers, visit),
+        }
+    }
+
+    /// Checks whether the line can be solved. A search cut short by the
+    /// deadline reports `false`.
+    fn is_solvable<N: Number>(&self, target: &N, numbers: &[N]) -> bool {
+        match self.arrangement {
+            Arrangement::InOrder => self.first_solution(target, numbers).is_some(),
+            _ => self.rearranged(target, numbers).solved().is_some(),
+        }
+    }
+
+    fn first_solution<N: Number>(&self, target: &N, numbers: &[N]) -> Option<Vec<Operator>> {
+        let mut first = None;
+        self.for_each_solution(target, numbers, &mut |assignment| {
+            first = Some(assignment.to_vec());
+            true
+        });
+        first
+    }
+
+    /// Looks for an arrangement of the operands that reaches `target` and
+    /// writes out the first one found. A search that would grow too large is
+    /// given up on before it runs out of memory.
+    fn rearranged<N: Number>(&self, target: &N, numbers: &[N]) -> Outcome<String> {
+        let found = match self.evaluation {
+            // No subset recurrence for mixed precedence, so try each order
+            Evaluation::Precedence if numbers.len() > MAX_ENUMERATED_OPERANDS => {
+                return Outcome::TooManyOperands;
+            }
+            Evaluation::Precedence => self.enumerate_arrangements(target, numbers),
+            _ => match SubsetSearch::new(target, numbers, self) {
+                Some(search) => search.find(target),
+                None => return Outcome::TooManyOperands,
+            },
+        };
+        found.map_or(Outcome::Unsolvable, Outcome::Solved)
+    }
+
+    /// Tries every distinct ordering of the operands (or of each selection of
+    /// them) with the in-order solver.
+    fn enumerate_arrangements<N: Number>(&self, target: &N, numbers: &[N]) -> Option<String> {
+        let in_order = Config {
+            arrangement: Arrangement::InOrder,
+            ..self.clone()
+        };
+        let lengths = match self.arrangement {
+            Arrangement::Subset => 1..=numbers.len(),
+            _ => numbers.len()..=numbers.len(),
+        };
+
+        let mut found = None;
+        for len in lengths {
+            let mut used = vec![false; numbers.len()];
+            permutations(numbers, len, &mut used, &mut Vec::new(), &mut |order| {
+                let assignment = in_order.first_solution(target, order);
+                found = assignment.map(|a| self.evaluation.format(target, order, &a));
+                found.is_some() || self.expired()
+            });
+            if found.is_some() {
                 break;
             }
         }
+        found
+    }
+
+    /// Counts the satisfying operator assignments and returns the first one.
+    /// With [`Evaluation::AnyParens`] an assignment counts once however many
+    /// bracketings make it work.
+    fn solutions<N: Number>(&self, target: &N, numbers: &[N]) -> (u64, Option<Vec<Operator>>) {
+        let mut count = 0;
+        let mut first = None;
+        self.for_each_solution(target, numbers, &mut |assignment| {
+            count += 1;
+            first.get_or_insert_with(|| assignment.to_vec());
+            false
+        });
+        (count, first)
+    }
+}
+
+/// How a subset of the operands produced a value.
+#[derive(Debug, Clone)]
+enum Step<N> {
+    Operand(usize),
+    /// `a op b`, with `a` made from the `left` subset and `b` from `right`.
+    Combine {
+        left: usize,
+        a: N,
+        op: Operator,
+        right: usize,
+        b: N,
+    },
+}
+
+/// Memoised search over subsets of the operands, each written as a bitmask
+/// of operand indices. For every subset it records each value the subset
+/// can produce and one way of producing it. Subsets are filled in
+/// increasing order, so the two halves of a split are always ready.
+///
+/// Left to right, a value of a subset is a value of the subset minus one
+/// operand combined with that operand on the right; right to left it goes
+/// on the left; with any brackets the subset may be split in two anywhere.
+struct SubsetSearch<'a, N> {
+    numbers: &'a [N],
+    arrangement: Arrangement,
+    evaluation: Evaluation,
+    table: Vec<BTreeMap<N, Step<N>>>,
+}
+
+impl<'a, N: Number> SubsetSearch<'a, N> {
+    /// Fills in the table, or returns `None` once it holds more than
+    /// [`MAX_TABLE_VALUES`] values.
+    fn new(target: &N, numbers: &'a [N], config: &Config) -> Option<Self> {
+        if numbers.len() > MAX_SUBSET_OPERANDS {
+            return None;
+        }
+        let full = 1usize << numbers.len();
+        // Values past the target stay past it when nothing can shrink them
+        let bound = (numbers.iter().all(|n| n.is_positive())
+            && config.ops.iter().all(|op| op.is_monotone()))
+        .then_some(target);
+        let mut table: Vec<BTreeMap<N, Step<N>>> = vec![BTreeMap::new(); full];
+        let mut stored = 0;
+
+        for mask in 1..full {
+            if config.expired() {
+                break;
+            }
+            if mask.is_power_of_two() {
+                let i = mask.trailing_zeros() as usize;
+                table[mask].insert(numbers[i].clone(), Step::Operand(i));
+                continue;
+            }
+
+            let mut values = BTreeMap::new();
+            for (left, right) in splits(mask, config.evaluation) {
+                for a in table[left].keys() {
+                    for b in table[right].keys() {
+                        for &op in &config.ops {
+                            if let Some(value) = op.apply(a, b)
+                                && bound.is_none_or(|bound| value <= *bound)
+                            {
+                                values.entry(value).or_insert_with(|| Step::Combine {
+                                    left,
+                                    a: a.clone(),
+                                    op,
+                                    right,
+                                    b: b.clone(),
+                                });
+                                if stored + values.len() > MAX_TABLE_VALUES {
+                                    return None;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            stored += values.len();
+            table[mask] = values;
+        }
+
+        Some(Self {
+            numbers,
+            arrangement: config.arrangement,
+            evaluation: config.evaluation,
+            table,
+        })
+    }
 
-        if valid {
-            count += *first;
+    /// Writes out a way to reach `target`, preferring the smallest subset
+    /// when operands may be left out.
+    fn find(&self, target: &N) -> Option<String> {
+        let full = self.table.len() - 1;
+        let mask = match self.arrangement {
+            Arrangement::Subset => (1..=full)
+                .filter(|&m| self.table[m].contains_key(target))
+                .min_by_key(|m| m.count_ones())?,
+            _ => full,
+        };
+        self.table[mask]
+            .contains_key(target)
+            .then(|| format!("{} = {}", target, self.format(mask, target)))
+    }
+
+    fn format(&self, mask: usize, value: &N) -> String {
+        match &self.table[mask][value] {
+            Step::Operand(i) => self.numbers[*i].to_string(),
+            Step::Combine {
+                left,
+                a,
+                op,
+                right,
+                b,
+            } => {
+                let nested = matches!(self.table[*right][b], Step::Combine { .. });
+                let (a, b) = (self.format(*left, a), self.format(*right, b));
+                match self.evaluation {
+                    Evaluation::AnyParens => format!("({} {} {})", a, op.symbol(), b),
+                    // A right deep tree needs its nested right operand bracketed
+                    Evaluation::RightToLeft if nested => format!("{} {} ({})", a, op.symbol(), b),
+                    // Left deep trees read correctly without brackets
+                    _ => format!("{} {} {}", a, op.symbol(), b),
+                }
+            }
         }
     }
+}
 
-    count
+/// Ways to split a subset into the two operands of its last operation.
+fn splits(mask: usize, evaluation: Evaluation) -> Vec<(usize, usize)> {
+    let singles = (0..usize::BITS)
+        .map(|i| 1usize << i)
+        .filter(|bit| mask & bit != 0);
+    match evaluation {
+        Evaluation::LeftToRight => singles.map(|bit| (mask ^ bit, bit)).collect(),
+        Evaluation::RightToLeft => singles.map(|bit| (bit, mask ^ bit)).collect(),
+        _ => {
+            // Every non-empty proper submask as the left half
+            let mut result = Vec::new();
+            let mut left = (mask - 1) & mask;
+            while left != 0 {
+                result.push((left, mask ^ left));
+                left = (left - 1) & mask;
+            }
+            result
+        }
+    }
 }
 
-fn part2(input: Vec<(i64, Vec<i64>)>) -> i64 {
-    let mut count: i64 = 0;
+/// Calls `visit` with each distinct ordering of `len` of the numbers, until
+/// it returns `true`. Equal numbers are not swapped with each other.
+fn permutations<N: Number>(
+    numbers: &[N],
+    len: usize,
+    used: &mut Vec<bool>,
+    order: &mut Vec<N>,
+    visit: &mut impl FnMut(&[N]) -> bool,
+) -> bool {
+    if order.len() == len {
+        return visit(order);
+    }
 
-    for (first, numbers) in input {
-        let m = numbers.len();
-        if m == 0 {
+    let mut tried: Vec<&N> = Vec::new();
+    for (i, number) in numbers.iter().enumerate() {
+        if used[i] || tried.contains(&number) {
             continue;
         }
+        tried.push(number);
+
+        used[i] = true;
+        order.push(number.clone());
+        let stopped = permutations(numbers, len, used, order, visit);
+        order.pop();
+        used[i] = false;
+
+        if stopped {
+            return true;
+        }
+    }
+    false
+}
+
+/// Enumerates every assignment of `ops` between the numbers and evaluates
+/// each one from scratch, see [`Config::for_each_solution`].
+fn brute_force<N: Number>(
+    config: &Config,
+    target: &N,
+    numbers: &[N],
+    visit: &mut impl FnMut(&[Operator]) -> bool,
+) -> bool {
+    let ops = &config.ops;
+    if numbers.is_empty() || (ops.is_empty() && numbers.len() > 1) {
+        return false;
+    }
+
+    let num_ops = numbers.len() - 1;
+    // Operator index for each gap, counted like an odometer in base `ops.len()`
+    let mut choice = vec![0; num_ops];
 
-        if m == 1 {
-            if numbers[0] == first {
-                count += 1;
+    loop {
+        let assignment: Vec<Operator> = choice.iter().map(|&c| ops[c]).collect();
+        if config.evaluation.reaches(target, numbers, &assignment) && visit(&assignment) {
+            return true;
+        }
+        if config.expired() {
+            return true;
+        }
+
+        // Advance to the next assignment, stopping once every one was tried
+        let mut i = 0;
+        loop {
+            if i == num_ops {
+                return false;
             }
-            continue;
+            choice[i] += 1;
+            if choice[i] < ops.len() {
+                break;
+            }
+            choice[i] = 0;
+            i += 1;
         }
+    }
+}
 
-        let mut valid = false;
-        let num_ops = m - 1;
+/// Searches the non-empty `numbers` for assignments that reach `target`, see
+/// [`Config::for_each_solution`]. `suffix` holds the operators already chosen
+/// for the gaps to the right, last gap first.
+///
+/// Works from the last operand backwards: for each operator, the target is
+/// reduced to the value the remaining prefix would have to produce. Most
+/// operators have at most two such values, so impossible branches (a
+/// remainder on division, a missing suffix on concatenation) are cut off
+/// without ever evaluating the prefix.
+fn backward<N: Number>(
+    config: &Config,
+    target: &N,
+    numbers: &[N],
+    suffix: &mut Vec<Operator>,
+    visit: &mut impl FnMut(&[Operator]) -> bool,
+) -> bool {
+    if config.expired() {
+        return true;
+    }
 
-        for mask in 0..(3_i64.pow(num_ops as u32) as usize) {
-            let mut ops = Vec::new();
-            for i in 0..num_ops {
-                let op_index = (mask / (3_i64.pow(i as u32)) as usize) % 3;
-                match op_index {
-                    0 => ops.push('+'),
-                    1 => ops.push('*'),
-                    2 => ops.push('|'),
-                    _ => panic!("Invalid operator"),
-                }
+    let (last, prefix) = numbers.split_last().unwrap();
+    if prefix.is_empty() {
+        if last != target {
+            return false;
+        }
+        let assignment: Vec<Operator> = suffix.iter().rev().copied().collect();
+        return visit(&assignment);
+    }
+
+    for &op in &config.ops {
+        suffix.push(op);
+        let stopped = match op.unapply(target, last) {
+            Inverse::None => false,
+            Inverse::One(a) => backward(config, &a, prefix, suffix, visit),
+            Inverse::Two(a, b) => {
+                backward(config, &a, prefix, suffix, visit)
+                    || backward(config, &b, prefix, suffix, visit)
             }
+            Inverse::Ambiguous => forward(config, prefix, &mut |value, path| {
+                if op.apply(value, last).as_ref() != Some(target) {
+                    return false;
+                }
+                let assignment: Vec<Operator> =
+                    path.iter().chain(suffix.iter().rev()).copied().collect();
+                visit(&assignment)
+            }),
+        };
+        suffix.pop();
 
-            let mut result = numbers[0];
-            for i in 0..num_ops {
-                let next = numbers[i + 1];
-                match ops[i] {
-                    '+' => result += next,
-                    '*' => result *= next,
-                    '|' => {
-                        let next_str = next.to_string();
-                        let result_str = result.to_string();
-                        let combined = format!("{}{}", result_str, next_str);
-                        result = combined.parse::<i64>().unwrap();
-                    },
-                    _ => panic!("Invalid operator"),
+        if stopped {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Evaluates the numbers left to right under every operator assignment and
+/// calls `accept` with each result and the assignment producing it, until
+/// `accept` returns `true`.
+fn forward<N: Number>(
+    config: &Config,
+    numbers: &[N],
+    accept: &mut impl FnMut(&N, &[Operator]) -> bool,
+) -> bool {
+    fn go<N: Number>(
+        config: &Config,
+        acc: &N,
+        rest: &[N],
+        path: &mut Vec<Operator>,
+        accept: &mut impl FnMut(&N, &[Operator]) -> bool,
+    ) -> bool {
+        let Some((next, rest)) = rest.split_first() else {
+            return accept(acc, path);
+        };
+        if config.expired() {
+            return true;
+        }
+
+        for &op in &config.ops {
+            if let Some(value) = op.apply(acc, next) {
+                path.push(op);
+                let stopped = go(config, &value, rest, path, accept);
+                path.pop();
+                if stopped {
+                    return true;
                 }
             }
+        }
+        false
+    }
 
-            if result == first {
-                valid = true;
-                break;
+    go(config, &numbers[0], &numbers[1..], &mut Vec::new(), accept)
+}
+
+/// Solves every line on `config.threads` worker threads and returns the
+/// results in input order, whichever order the workers finish in.
+fn solve_lines<N: Number, T: Send>(
+    input: &[Equation<N>],
+    config: &Config,
+    solve: impl Fn(&N, &[N]) -> T + Sync,
+) -> Vec<T> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, T)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let line = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((_, target, numbers)) = input.get(line) else {
+                            break;
+                        };
+                        done.push((line, solve(target, numbers)));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Worker thread panicked"))
+            .collect()
+    });
+
+    results.sort_by_key(|(line, _)| *line);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Joins input file line numbers for printing.
+fn format_lines(lines: &[usize]) -> String {
+    let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    lines.join(", ")
+}
+
+/// Prints a satisfying expression and the number of solutions for each line,
+/// followed by the lines that cannot be solved, those that ran out of time
+/// and those with too many operands to rearrange. Solutions are not counted
+/// when operands may be rearranged.
+fn report<N: Number>(input: &[Equation<N>], config: &Config) {
+    let outcomes = solve_lines(input, config, |target, numbers| {
+        let config = config.start_line();
+        let found = if config.arrangement != Arrangement::InOrder {
+            config.rearranged(target, numbers)
+        } else {
+            let (count, first) = config.solutions(target, numbers);
+            first.map_or(Outcome::Unsolvable, |assignment| {
+                Outcome::Solved(format!(
+                    "{} ({} solution{})",
+                    config.evaluation.format(target, numbers, &assignment),
+                    count,
+                    if count == 1 { "" } else { "s" }
+                ))
+            })
+        };
+
+        // A count cut short by the deadline would be wrong, so drop it
+        match found {
+            Outcome::Solved(_) | Outcome::Unsolvable if config.expired() => Outcome::TimedOut,
+            found => found,
+        }
+    });
+
+    let mut unsolvable = Vec::new();
+    let mut timed_out = Vec::new();
+    let mut too_many = Vec::new();
+    for ((line, _, _), outcome) in input.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Solved(equation) => println!("Line {}: {}", line, equation),
+            Outcome::Unsolvable => unsolvable.push(*line),
+            Outcome::TimedOut => timed_out.push(*line),
+            Outcome::TooManyOperands => too_many.push(*line),
+        }
+    }
+
+    println!(
+        "Unsolvable lines ({}): {}",
+        unsolvable.len(),
+        format_lines(&unsolvable)
+    );
+    if !timed_out.is_empty() {
+        println!(
+            "Lines that ran out of time ({}): {}",
+            timed_out.len(),
+            format_lines(&timed_out)
+        );
+    }
+    if !too_many.is_empty() {
+        println!(
+            "Lines with too many operands to rearrange ({}): {}",
+            too_many.len(),
+            format_lines(&too_many)
+        );
+    }
+}
+
+/// A calibration total, followed by the lines left out of it because they
+/// ran out of time and because they had too many operands to rearrange.
+type Total<N> = (N, Vec<usize>, Vec<usize>);
+
+/// Sums the targets of every equation that can be made true under `config`.
+/// Lines given up on are left out of the sum and returned instead.
+fn total_calibration<N: Number>(input: &[Equation<N>], config: &Config) -> Total<N> {
+    let outcomes = solve_lines(input, config, |target, numbers| {
+        config.outcome(target, numbers)
+    });
+
+    let mut total = N::zero();
+    let mut timed_out = Vec::new();
+    let mut too_many = Vec::new();
+    for ((line, target, _), outcome) in input.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Solved(()) => {
+                total = total
+                    .checked_add(target)
+                    .expect("Calibration total overflows (try --bigint)");
             }
+            Outcome::Unsolvable => {}
+            Outcome::TimedOut => timed_out.push(*line),
+            Outcome::TooManyOperands => too_many.push(*line),
         }
+    }
+    (total, timed_out, too_many)
+}
 
-        if valid {
-            count += first;
+/// Prints a calibration total and any lines that were given up on.
+fn print_total<N: Number>(label: &str, (total, timed_out, too_many): Total<N>) {
+    println!("{}: {}", label, total);
+    if !timed_out.is_empty() {
+        println!(
+            "  ran out of time on {} lines: {}",
+            timed_out.len(),
+            format_lines(&timed_out)
+        );
+    }
+    if !too_many.is_empty() {
+        println!(
+            "  too many operands to rearrange on {} lines: {}",
+            too_many.len(),
+            format_lines(&too_many)
+        );
+    }
+}
+
+/// Runs both solvers on every line and reports any line where they disagree.
+/// Also checks that any line solvable under a fixed evaluation order is
+/// solvable when brackets may be placed freely, since every fixed order is
+/// itself a bracketing, and cross-checks the rearranging modes.
+fn verify<N: Number>(input: &[Equation<N>], ops: &[Operator]) -> bool {
+    let brute_force = Config::new(ops, Solver::BruteForce);
+    let backward = Config::new(ops, Solver::Backward);
+    let fixed_orders = [
+        Evaluation::LeftToRight,
+        Evaluation::RightToLeft,
+        Evaluation::Precedence,
+    ];
+    let any_parens = Config {
+        evaluation: Evaluation::AnyParens,
+        ..brute_force.clone()
+    };
+
+    let mut ok = true;
+    for (_, target, numbers) in input {
+        let (expected, _) = brute_force.solutions(target, numbers);
+        let (actual, _) = backward.solutions(target, numbers);
+        if expected != actual {
+            println!(
+                "Mismatch for {}: {:?} (brute force {} solutions, backward {})",
+                target, numbers, expected, actual
+            );
+            ok = false;
+        }
+
+        // Rearranging can only help, and the subset search must agree with
+        // trying every ordering
+        let mut previous = expected > 0;
+        for arrangement in [Arrangement::Permuted, Arrangement::Subset] {
+            let rearranged = Config {
+                arrangement,
+                ..brute_force.clone()
+            };
+            // Trying every order gets out of hand just like the table does
+            if numbers.len() > MAX_ENUMERATED_OPERANDS {
+                continue;
+            }
+            let searched = match rearranged.rearranged(target, numbers) {
+                Outcome::TooManyOperands => continue,
+                found => matches!(found, Outcome::Solved(_)),
+            };
+            let enumerated = rearranged.enumerate_arrangements(target, numbers).is_some();
+            if searched != enumerated || (previous && !searched) {
+                println!(
+                    "Mismatch for {}: {:?} ({:?} search {}, enumeration {})",
+                    target, numbers, arrangement, searched, enumerated
+                );
+                ok = false;
+            }
+            previous = searched;
+        }
+
+        let bracketed = any_parens.is_solvable(target, numbers);
+        for evaluation in fixed_orders {
+            let fixed = Config {
+                evaluation,
+                ..brute_force.clone()
+            };
+            if fixed.is_solvable(target, numbers) && !bracketed {
+                println!(
+                    "Mismatch for {}: {:?} (solvable with {:?} but not with any brackets)",
+                    target, numbers, evaluation
+                );
+                ok = false;
+            }
         }
     }
+    ok
+}
+
+fn part1<N: Number>(input: &[Equation<N>], base: &Config) -> Total<N> {
+    let config = Config {
+        ops: vec![Operator::Add, Operator::Mul],
+        ..base.clone()
+    };
+    total_calibration(input, &config)
+}
+
+fn part2<N: Number>(input: &[Equation<N>], base: &Config) -> Total<N> {
+    let config = Config {
+        ops: vec![Operator::Add, Operator::Mul, Operator::Concat],
+        ..base.clone()
+    };
+    total_calibration(input, &config)
+}
 
-    count
+/// Returns the value following `flag` on the command line, if present.
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|pos| args.get(pos + 1))
+        .map(String::as_str)
+}
+
+fn run<N: Number>(args: &[String]) {
+    let input = read_input::<N>();
+
+    // `--solver brute|backward` picks the search strategy
+    let solver = arg_value(args, "--solver")
+        .map_or(Ok(Solver::Backward), Solver::from_str)
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    // `--threads N` and `--timeout SECONDS` control how lines are run
+    let threads = arg_value(args, "--threads").map_or_else(
+        || thread::available_parallelism().map_or(1, usize::from),
+        |n| n.parse().expect("Invalid thread count"),
+    );
+    let timeout = arg_value(args, "--timeout")
+        .map(|secs| Duration::from_secs_f64(secs.parse().expect("Invalid timeout")));
+    let base = Config {
+        threads,
+        timeout,
+        ..Config::new(&[], solver)
+    };
+
+    print_total("Part 1 result", part1(&input, &base));
+    print_total("Part 2 result", part2(&input, &base));
+
+    // `--ops add,mul,...`, `--eval ltr|rtl|precedence|parens` and
+    // `--arrange in-order|permuted|subset` solve with a custom operator set,
+    // evaluation order and operand arrangement
+    let custom_ops = arg_value(args, "--ops")
+        .map(|list| parse_operators(list).unwrap_or_else(|e| panic!("{}", e)));
+    let evaluation = arg_value(args, "--eval")
+        .map(|mode| Evaluation::from_str(mode).unwrap_or_else(|e| panic!("{}", e)));
+    let arrangement = arg_value(args, "--arrange")
+        .map(|mode| Arrangement::from_str(mode).unwrap_or_else(|e| panic!("{}", e)));
+    let ops = custom_ops
+        .clone()
+        .unwrap_or_else(|| vec![Operator::Add, Operator::Mul, Operator::Concat]);
+    let config = Config {
+        evaluation: evaluation.unwrap_or(Evaluation::LeftToRight),
+        arrangement: arrangement.unwrap_or(Arrangement::InOrder),
+        ops: ops.clone(),
+        ..base
+    };
+
+    if custom_ops.is_some() || evaluation.is_some() || arrangement.is_some() {
+        let names: Vec<&str> = ops.iter().map(|op| op.name()).collect();
+        let label = format!(
+            "Result with [{}], {:?}, {:?}",
+            names.join(", "),
+            config.evaluation,
+            config.arrangement
+        );
+        print_total(&label, total_calibration(&input, &config));
+    }
+
+    // `--report` shows a solution and the solution count for every line
+    if args.iter().any(|a| a == "--report") {
+        report(&input, &config);
+    }
+
+    // `--verify` checks the backward solver against the brute force one
+    if args.iter().any(|a| a == "--verify") && verify(&input, &ops) {
+        println!(
+            "Solvers and evaluation modes agree on all {} lines",
+            input.len()
+        );
+    }
 }
 
 fn main() {
-    let input = read_input();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `--bigint` switches to arbitrary precision for targets beyond 64 bits
+    if args.iter().any(|a| a == "--bigint") {
+        run::<BigInt>(&args);
+    } else {
+        run::<i64>(&args);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::iter::Peekable;
+
+    const OPS: [Operator; 4] = [
+        Operator::Add,
+        Operator::Mul,
+        Operator::Concat,
+        Operator::Sub,
+    ];
+    const MODES: [Evaluation; 4] = [
+        Evaluation::LeftToRight,
+        Evaluation::RightToLeft,
+        Evaluation::Precedence,
+        Evaluation::AnyParens,
+    ];
+
+    /// Every operand list of up to `len` numbers drawn from `0..4`.
+    fn operand_sets(len: usize) -> Vec<Vec<i64>> {
+        let mut sets = vec![vec![]];
+        let mut all = Vec::new();
+        for _ in 0..len {
+            sets = sets
+                .iter()
+                .flat_map(|set| {
+                    (0..4).map(move |n| {
+                        let mut next = set.clone();
+                        next.push(n);
+                        next
+                    })
+                })
+                .collect();
+            all.extend(sets.iter().cloned());
+        }
+        all
+    }
+
+    fn assignments(len: usize) -> Vec<Vec<Operator>> {
+        assignments_of(&OPS, len)
+    }
+
+    fn assignments_of(ops: &[Operator], len: usize) -> Vec<Vec<Operator>> {
+        (0..len).fold(vec![vec![]], |acc, _| {
+            acc.iter()
+                .flat_map(|a| {
+                    ops.iter().map(move |&op| {
+                        let mut next = a.clone();
+                        next.push(op);
+                        next
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// Evaluates the expression the way `evaluation` groups it, written
+    /// out as recursion rather than the loops and stacks of the solvers.
+    fn evaluate(evaluation: Evaluation, numbers: &[i64], assignment: &[Operator]) -> Option<i64> {
+        if numbers.len() == 1 {
+            return Some(numbers[0]);
+        }
+        let split = match evaluation {
+            Evaluation::LeftToRight => assignment.len() - 1,
+            Evaluation::RightToLeft => 0,
+            // The last of the loosest operators is applied last
+            _ => (0..assignment.len())
+                .rev()
+                .min_by_key(|&i| assignment[i].precedence())
+                .unwrap(),
+        };
+        let a = evaluate(evaluation, &numbers[..=split], &assignment[..split])?;
+        let b = evaluate(evaluation, &numbers[split + 1..], &assignment[split + 1..])?;
+        assignment[split].apply(&a, &b)
+    }
 
-    let result = part1(&input);
-    println!("Part 1 result: {}", result);
+    /// Every value some bracketing of the expression takes.
+    fn bracketings(numbers: &[i64], assignment: &[Operator]) -> BTreeSet<i64> {
+        if numbers.len() == 1 {
+            return BTreeSet::from([numbers[0]]);
+        }
+        let mut values = BTreeSet::new();
+        for split in 0..assignment.len() {
+            let left = bracketings(&numbers[..=split], &assignment[..split]);
+            let right = bracketings(&numbers[split + 1..], &assignment[split + 1..]);
+            for a in &left {
+                for b in &right {
+                    values.extend(assignment[split].apply(a, b));
+                }
+            }
+        }
+        values
+    }
+
+    fn reachable(evaluation: Evaluation, numbers: &[i64]) -> BTreeSet<i64> {
+        let mut values = BTreeSet::new();
+        for assignment in assignments(numbers.len() - 1) {
+            if evaluation == Evaluation::AnyParens {
+                values.extend(bracketings(numbers, &assignment));
+            } else {
+                values.extend(evaluate(evaluation, numbers, &assignment));
+            }
+        }
+        values
+    }
+
+    /// Reads back a written out solution, applying operators left to right
+    /// unless brackets say otherwise.
+    fn parse_solution(solution: &str) -> (i64, i64) {
+        fn expression<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> i64 {
+            let mut value = operand(tokens);
+            while let Some(symbol) = tokens.next_if(|&t| t != ")") {
+                let op: Operator = symbol.parse().unwrap();
+                value = op.apply(&value, &operand(tokens)).unwrap();
+            }
+            value
+        }
+
+        fn operand<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> i64 {
+            match tokens.next().unwrap() {
+                "(" => {
+                    let value = expression(tokens);
+                    assert_eq!(tokens.next(), Some(")"));
+                    value
+                }
+                number => number.parse().unwrap(),
+            }
+        }
+
+        let (target, expr) = solution.split_once(" = ").unwrap();
+        let spaced = expr.replace('(', "( ").replace(')', " )");
+        let mut tokens = spaced.split_whitespace().peekable();
+        (target.parse().unwrap(), expression(&mut tokens))
+    }
+
+    fn config(evaluation: Evaluation, arrangement: Arrangement) -> Config {
+        Config {
+            evaluation,
+            arrangement,
+            ..Config::new(&OPS, Solver::BruteForce)
+        }
+    }
 
-    let result = part2(input);
-    println!("Part 2 result: {}", result);
+    #[test]
+    fn right_to_left_solutions_are_bracketed() {
+        let ops = [Operator::Sub];
+        let rtl = Config {
+            evaluation: Evaluation::RightToLeft,
+            ..Config::new(&ops, Solver::BruteForce)
+        };
+        let numbers = [2, 3, 4];
+        let assignment = rtl.first_solution(&3, &numbers).unwrap();
+        assert_eq!(
+            Evaluation::RightToLeft.format(&3, &numbers, &assignment),
+            "3 = 2 - (3 - 4)"
+        );
+
+        let permuted = Config {
+            arrangement: Arrangement::Permuted,
+            ..rtl
+        };
+        assert_eq!(
+            permuted.rearranged(&-2, &[2, 3, 4, 5]).solved().as_deref(),
+            Some("-2 = 2 - (3 - (4 - 5))")
+        );
+    }
+
+    #[test]
+    fn evaluation_modes_match_exhaustive_enumeration() {
+        for numbers in operand_sets(4) {
+            for evaluation in MODES {
+                let config = config(evaluation, Arrangement::InOrder);
+                let expected = reachable(evaluation, &numbers);
+                for target in -20..=40 {
+                    assert_eq!(
+                        config.is_solvable(&target, &numbers),
+                        expected.contains(&target),
+                        "{:?} {} {:?}",
+                        evaluation,
+                        target,
+                        numbers
+                    );
+                }
+                for target in &expected {
+                    let assignment = config.first_solution(target, &numbers).unwrap();
+                    assert!(evaluation.reaches(target, &numbers, &assignment));
+                    // Precedence output relies on the precedence rules instead
+                    if evaluation != Evaluation::Precedence {
+                        let solution = evaluation.format(target, &numbers, &assignment);
+                        assert_eq!(
+                            parse_solution(&solution),
+                            (*target, *target),
+                            "{}",
+                            solution
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rearranged_modes_match_exhaustive_enumeration() {
+        for numbers in operand_sets(3) {
+            for evaluation in MODES {
+                let mut orders = Vec::new();
+                for len in 1..=numbers.len() {
+                    let mut used = vec![false; numbers.len()];
+                    permutations(&numbers, len, &mut used, &mut Vec::new(), &mut |order| {
+                        orders.push(order.to_vec());
+                        false
+                    });
+                }
+                let reach = |order: &Vec<i64>| reachable(evaluation, order);
+                let permuted: BTreeSet<i64> = orders
+                    .iter()
+                    .filter(|order| order.len() == numbers.len())
+                    .flat_map(reach)
+                    .collect();
+                let subset: BTreeSet<i64> = orders.iter().flat_map(reach).collect();
+
+                for (arrangement, expected) in [
+                    (Arrangement::Permuted, permuted),
+                    (Arrangement::Subset, subset),
+                ] {
+                    let config = config(evaluation, arrangement);
+                    for target in -10..=40 {
+                        let found = config.rearranged(&target, &numbers).solved();
+                        assert_eq!(
+                            found.is_some(),
+                            expected.contains(&target),
+                            "{:?} {:?} {} {:?}",
+                            evaluation,
+                            arrangement,
+                            target,
+                            numbers
+                        );
+                        if let Some(solution) = found
+                            && evaluation != Evaluation::Precedence
+                        {
+                            assert_eq!(parse_solution(&solution), (target, target), "{}", solution);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn concat_edge_cases() {
+        assert_eq!(concat_shift(&0i64), Some(10));
+        assert_eq!(concat_shift(&9i64), Some(10));
+        assert_eq!(concat_shift(&10i64), Some(100));
+        assert_eq!(concat(&0i64, &5), Some(5));
+        assert_eq!(concat(&12i64, &0), Some(120));
+        assert_eq!(concat(&12i64, &345), Some(12345));
+        assert_eq!(concat(&-1i64, &5), None);
+
+        assert_eq!(split_concat(&120i64, &0), Some(12));
+        assert_eq!(split_concat(&5i64, &5), Some(0));
+        assert_eq!(split_concat(&12345i64, &345), Some(12));
+        assert_eq!(split_concat(&3i64, &45), None);
+        assert_eq!(split_concat(&1234i64, &5), None);
+    }
+
+    #[test]
+    fn concat_overflow_boundary() {
+        const MAX: i64 = i64::MAX;
+        const E18: i64 = 1_000_000_000_000_000_000;
+        // 10^19 does not fit, so no shift exists for 19 digit operands
+        assert_eq!(concat_shift(&(E18 - 1)), Some(E18));
+        assert_eq!(concat_shift(&E18), None);
+        assert_eq!(concat_shift(&MAX), None);
+
+        assert_eq!(concat(&(MAX / 10), &7), Some(MAX));
+        assert_eq!(concat(&(MAX / 10), &8), None);
+        assert_eq!(concat(&1, &(E18 - 1)), Some(2 * E18 - 1));
+        assert_eq!(concat(&9, &(E18 - 1)), None);
+        assert_eq!(concat(&0, &MAX), Some(MAX));
+        assert_eq!(concat(&1, &MAX), None);
+
+        assert_eq!(split_concat(&MAX, &7), Some(MAX / 10));
+        assert_eq!(split_concat(&MAX, &MAX), Some(0));
+        assert_eq!(split_concat(&MAX, &E18), None);
+
+        // Big integers carry on where i64 stops
+        let big = |n: i64| BigInt::from(n);
+        assert_eq!(
+            concat(&big(9), &big(E18 - 1)),
+            "9999999999999999999".parse().ok()
+        );
+        assert_eq!(
+            split_concat(&"9999999999999999999".parse().unwrap(), &big(E18 - 1)),
+            Some(big(9))
+        );
+    }
+
+    #[test]
+    fn backward_counts_match_brute_force() {
+        let brute_force = Config::new(&Operator::ALL, Solver::BruteForce);
+        let backward = Config::new(&Operator::ALL, Solver::Backward);
+        // Zeros make multiplication, powers and max ambiguous to undo
+        let mut lines = operand_sets(4);
+        lines.extend([
+            vec![0, 2, 0, 3, 0],
+            vec![3, 0, 0, 2, 1],
+            vec![1, 0, 3, 2, 0],
+            vec![2, 0, 1, 0, 3],
+        ]);
+
+        for numbers in lines {
+            let mut targets: BTreeSet<i64> = (-5..=30).collect();
+            for assignment in assignments_of(&Operator::ALL, numbers.len() - 1) {
+                targets.extend(evaluate(Evaluation::LeftToRight, &numbers, &assignment));
+            }
+            for target in targets {
+                assert_eq!(
+                    backward.solutions(&target, &numbers).0,
+                    brute_force.solutions(&target, &numbers).0,
+                    "{} {:?}",
+                    target,
+                    numbers
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pruned_subset_search_keeps_every_solution() {
+        let ops = [
+            Operator::Add,
+            Operator::Mul,
+            Operator::Concat,
+            Operator::Max,
+        ];
+        for numbers in operand_sets(4) {
+            if numbers.contains(&0) {
+                continue;
+            }
+            for arrangement in [Arrangement::Permuted, Arrangement::Subset] {
+                let config = Config {
+                    arrangement,
+                    ..Config::new(&ops, Solver::BruteForce)
+                };
+                for target in (1..=40).chain([123, 312, 1111, 3321]) {
+                    assert_eq!(
+                        config.rearranged(&target, &numbers).solved().is_some(),
+                        config.enumerate_arrangements(&target, &numbers).is_some(),
+                        "{:?} {} {:?}",
+                        arrangement,
+                        target,
+                        numbers
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rearranging_stops_where_the_search_grows_too_large() {
+        let rearranged = |ops: &[Operator], evaluation, arrangement| Config {
+            evaluation,
+            arrangement,
+            ..Config::new(ops, Solver::Backward)
+        };
+        let arrangements = [Arrangement::Permuted, Arrangement::Subset];
+
+        // Pruned by the target, a long add and mul line stays small
+        let numbers: Vec<i64> = (1..=12).collect();
+        for arrangement in arrangements {
+            let config = rearranged(
+                &[Operator::Add, Operator::Mul],
+                Evaluation::LeftToRight,
+                arrangement,
+            );
+            assert_eq!(config.outcome(&78, &numbers), Outcome::Solved(()));
+        }
+
+        // Subtraction keeps every value, and concatenation makes many
+        let numbers = [13, 7, 91, 42, 5, 68, 29, 84, 3, 57];
+        let in_order = rearranged(&OPS, Evaluation::LeftToRight, Arrangement::InOrder);
+        assert_ne!(in_order.outcome(&1, &numbers), Outcome::TooManyOperands);
+        for arrangement in arrangements {
+            let config = rearranged(&OPS, Evaluation::LeftToRight, arrangement);
+            assert_eq!(config.outcome(&1, &numbers), Outcome::TooManyOperands);
+        }
+
+        // Past these lengths nothing is tried at all
+        let ones = vec![1; MAX_SUBSET_OPERANDS + 1];
+        let config = rearranged(
+            &[Operator::Mul],
+            Evaluation::LeftToRight,
+            Arrangement::Permuted,
+        );
+        assert_eq!(config.outcome(&1, &ones), Outcome::TooManyOperands);
+        let ones = vec![1; MAX_ENUMERATED_OPERANDS + 1];
+        let config = rearranged(
+            &[Operator::Mul],
+            Evaluation::Precedence,
+            Arrangement::Permuted,
+        );
+        assert_eq!(config.outcome(&1, &ones), Outcome::TooManyOperands);
+    }
 }
@@ -1,7 +1,12 @@
-use std::collections::{HashSet, HashMap};
+use num_bigint::BigInt;
+use num_integer::{ExtendedGcd, Integer};
+use num_traits::Zero;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::ops::Neg;
 use std::path::Path;
+use std::str::FromStr;
 
 struct AntennaMap {
     nrows: usize,
@@ -12,7 +17,7 @@ struct AntennaMap {
 fn read_input() -> AntennaMap {
     let path = Path::new("input/day8.txt");
 
-    let file = File::open(&path).expect("Failed to open file");
+    let file = File::open(path).expect("Failed to open file");
     let reader = BufReader::new(file);
 
     let lines: Vec<String> = reader
@@ -47,78 +52,391 @@ fn is_antenna(c: char) -> bool {
     c.is_ascii_alphanumeric()
 }
 
-fn part1(input: &AntennaMap) -> usize {
-    let mut counted = HashSet::new();
+/// A signed lattice point. Map cells are points with the row as `x`, the
+/// column as `y` and `z` at zero, so antinodes off the map stay representable
+/// until they are converted back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Point {
+    x: i64,
+    y: i64,
+    z: i64,
+}
 
-    for (_c, positions) in &input.positions {
-        let len = positions.len();
-        for i in 0..len {
-            for j in 0..len {
-                if i == j {
-                    continue;
-                }
-                let a = positions[i];
-                let b = positions[j];
-                // Compute the third point that is collinear and at twice the distance
-                let x = 2 * b.0 as i32 - a.0 as i32;
-                let y = 2 * b.1 as i32 - a.1 as i32;
-
-                // Handle the case where x and y are negative
-                if x < 0 || y < 0 {
-                    continue;
-                }
+/// The offset from one point to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Vector {
+    dx: i64,
+    dy: i64,
+    dz: i64,
+}
+
+impl Point {
+    fn from_coords([x, y, z]: [i64; 3]) -> Self {
+        Point { x, y, z }
+    }
+
+    fn coords(&self) -> [i64; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    fn from_cell((row, col): (usize, usize)) -> Self {
+        Point {
+            x: i64::try_from(row).expect("Map too large"),
+            y: i64::try_from(col).expect("Map too large"),
+            z: 0,
+        }
+    }
+
+    /// The cell of an `nrows` by `ncols` map at this point, if it is on it.
+    fn to_cell(self, nrows: usize, ncols: usize) -> Option<(usize, usize)> {
+        if self.z != 0 {
+            return None;
+        }
+        let row = usize::try_from(self.x).ok().filter(|&row| row < nrows)?;
+        let col = usize::try_from(self.y).ok().filter(|&col| col < ncols)?;
+        Some((row, col))
+    }
+
+    /// The vector from `origin` to this point, if it fits.
+    fn checked_sub(&self, origin: &Point) -> Option<Vector> {
+        Some(Vector {
+            dx: self.x.checked_sub(origin.x)?,
+            dy: self.y.checked_sub(origin.y)?,
+            dz: self.z.checked_sub(origin.z)?,
+        })
+    }
+
+    /// `self + k * v`, if it is still a representable point.
+    fn checked_offset(&self, v: &Vector, k: i128) -> Option<Point> {
+        let mut coords = self.coords();
+        for (coord, d) in coords.iter_mut().zip(v.components()) {
+            let moved = (d as i128).checked_mul(k)?.checked_add(*coord as i128)?;
+            *coord = i64::try_from(moved).ok()?;
+        }
+        Some(Point::from_coords(coords))
+    }
 
-                let c_point = (x as usize, y as usize);
+    /// The exact difference `self - origin`, however far apart they are.
+    fn wide_sub(&self, origin: &Point) -> [i128; 3] {
+        [0, 1, 2].map(|axis| self.coords()[axis] as i128 - origin.coords()[axis] as i128)
+    }
+}
+
+impl Vector {
+    fn from_components([dx, dy, dz]: [i64; 3]) -> Self {
+        Vector { dx, dy, dz }
+    }
+
+    fn components(&self) -> [i64; 3] {
+        [self.dx, self.dy, self.dz]
+    }
+
+    fn wide(&self) -> [i128; 3] {
+        self.components().map(i128::from)
+    }
+
+    /// The shortest lattice vector pointing the same way.
+    fn reduced(&self) -> Vector {
+        let g = self.dx.gcd(&self.dy).gcd(&self.dz);
+        assert!(g != 0, "A zero vector has no direction");
+        Vector::from_components(self.components().map(|d| d / g))
+    }
+
+    /// `self * num / den`, if that is a whole, representable vector.
+    fn scaled(&self, num: i64, den: i64) -> Option<Vector> {
+        let mut components = [0; 3];
+        for (component, d) in components.iter_mut().zip(self.wide()) {
+            let scaled = d.checked_mul(num as i128)?;
+            if scaled % den as i128 != 0 {
+                return None;
+            }
+            *component = i64::try_from(scaled / den as i128).ok()?;
+        }
+        Some(Vector::from_components(components))
+    }
+}
+
+impl Neg for Vector {
+    type Output = Vector;
+
+    fn neg(self) -> Vector {
+        Vector::from_components(self.components().map(|d| -d))
+    }
+}
+
+fn cross(u: &[i128; 3], v: &[i128; 3]) -> [i128; 3] {
+    let mul = |a: i128, b: i128| a.checked_mul(b).expect("Coordinates too large");
+    [
+        mul(u[1], v[2]) - mul(u[2], v[1]),
+        mul(u[2], v[0]) - mul(u[0], v[2]),
+        mul(u[0], v[1]) - mul(u[1], v[0]),
+    ]
+}
 
-                // Check if the point is within bounds and exists in the current positions
-                if c_point.0 < input.nrows && c_point.1 < input.ncols {
+fn dot(u: &[i128; 3], v: &[i128; 3]) -> i128 {
+    (0..3).fold(0i128, |sum, axis| {
+        u[axis]
+            .checked_mul(v[axis])
+            .and_then(|product| sum.checked_add(product))
+            .expect("Coordinates too large")
+    })
+}
+
+/// How the resonant harmonics of part 2 walk the line through two antennas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineMode {
+    /// Step by the raw offset between the antennas, as the puzzle expects.
+    Puzzle,
+    /// Step by the offset divided by its gcd, reaching every grid point on
+    /// the line in both directions.
+    Lattice,
+}
+
+impl FromStr for LineMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "puzzle" => Ok(LineMode::Puzzle),
+            "lattice" => Ok(LineMode::Lattice),
+            _ => Err(format!("Unknown line mode: {}", s)),
+        }
+    }
+}
+
+/// Antinodes sit on the line through two antennas where the distance to one
+/// antenna is `near / far` times the distance to the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ratio {
+    near: i64,
+    far: i64,
+}
+
+impl FromStr for Ratio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (near, far) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Ratio must look like 1:2, got {}", s))?;
+        let near: i64 = near
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid ratio: {}", s))?;
+        let far: i64 = far
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid ratio: {}", s))?;
+        if near <= 0 || far <= 0 {
+            return Err(format!("Ratio terms must be positive: {}", s));
+        }
+        // Normalise so `near` is the shorter distance
+        Ok(Ratio {
+            near: near.min(far),
+            far: near.max(far),
+        })
+    }
+}
+
+impl Ratio {
+    /// Positions along the line from `a` to `b`, as fractions `(num, den)` of
+    /// the offset `b - a`, whose distance to `b` is `near / far` times the
+    /// distance to `a`.
+    fn offsets(&self, internal: bool) -> Vec<(i64, i64)> {
+        let mut offsets = Vec::new();
+        // Beyond `b`: |t| = far / (far - near), undefined for equal terms
+        if self.far != self.near {
+            offsets.push((self.far, self.far - self.near));
+        }
+        // Between the antennas: t = far / (far + near)
+        if internal {
+            offsets.push((self.far, self.far + self.near));
+        }
+        offsets
+    }
+}
+
+/// Which antinodes part 1 counts for every ordered pair of antennas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AntinodeRule {
+    ratios: Vec<Ratio>,
+    /// Also count the antinodes between the two antennas.
+    internal: bool,
+}
+
+impl Default for AntinodeRule {
+    /// The puzzle rule: one antenna twice as far away as the other, outside
+    /// the pair only.
+    fn default() -> Self {
+        AntinodeRule {
+            ratios: vec![Ratio { near: 1, far: 2 }],
+            internal: false,
+        }
+    }
+}
+
+/// A set of map cells.
+type Cells = HashSet<(usize, usize)>;
+
+/// Antinodes of a single frequency, keyed by frequency character.
+type FrequencyAntinodes = HashMap<char, Cells>;
+
+/// The points antennas of one frequency produce: for every ordered pair `a`,
+/// `b` and every `(num, den)` in `offsets`, the cell `a + (b - a) * num / den`
+/// when that lands on a whole cell of the map.
+fn ratio_antinodes(
+    input: &AntennaMap,
+    positions: &[(usize, usize)],
+    offsets: &[(i64, i64)],
+) -> HashSet<(usize, usize)> {
+    let mut counted = HashSet::new();
+
+    let len = positions.len();
+    for i in 0..len {
+        for j in 0..len {
+            if i == j {
+                continue;
+            }
+            let a = Point::from_cell(positions[i]);
+            let b = Point::from_cell(positions[j]);
+            let d = b.checked_sub(&a).expect("Map too large");
+
+            for &(num, den) in offsets {
+                // Only whole grid points on the map can hold an antinode
+                if let Some(c_point) = d
+                    .scaled(num, den)
+                    .and_then(|v| a.checked_offset(&v, 1))
+                    .and_then(|p| p.to_cell(input.nrows, input.ncols))
+                {
                     counted.insert(c_point);
                 }
             }
         }
     }
 
+    counted
+}
+
+fn part1_by_frequency(input: &AntennaMap, rule: &AntinodeRule) -> FrequencyAntinodes {
+    let offsets: Vec<(i64, i64)> = rule
+        .ratios
+        .iter()
+        .flat_map(|ratio| ratio.offsets(rule.internal))
+        .collect();
+
+    input
+        .positions
+        .iter()
+        .map(|(&c, positions)| (c, ratio_antinodes(input, positions, &offsets)))
+        .collect()
+}
+
+fn part1(input: &AntennaMap, rule: &AntinodeRule) -> usize {
+    let counted: HashSet<_> = part1_by_frequency(input, rule)
+        .into_values()
+        .flatten()
+        .collect();
+
     counted.len()
 }
 
-fn part2(input: &AntennaMap) -> usize {
-    let mut counted = HashSet::new();
+/// Map cells `a + k * step` for `k = first, first + 1, ...`, up to the edge
+/// of the map. The map is convex, so once the walk leaves it it never returns.
+fn walk(input: &AntennaMap, a: &Point, step: &Vector, first: i128) -> Vec<(usize, usize)> {
+    (first..)
+        .map_while(|k| {
+            a.checked_offset(step, k)
+                .and_then(|p| p.to_cell(input.nrows, input.ncols))
+        })
+        .collect()
+}
 
-    for (_c, positions) in &input.positions {
-        let len = positions.len();
-        for i in 0..len {
-            for j in 0..len {
-                if i == j {
-                    continue;
-                }
-                let a = positions[i];
-                let b = positions[j];
-                // Compute points at various scalar multiples of the vector from a to b
-                for k in 2.. {  // Remove the upper limit only exit if break
-                    let x = k as i32 * b.0 as i32 - (k - 1) as i32 * a.0 as i32;
-                    let y = k as i32 * b.1 as i32 - (k - 1) as i32 * a.1 as i32;
-
-                    // Ensure x and y are non-negative before conversion
-                    if x < 0 || y < 0 {
-                        break; // Skip invalid points
-                    }
+/// The harmonics of the puzzle: multiples of the raw offset beyond `b`.
+fn puzzle_line(input: &AntennaMap, a: (usize, usize), b: (usize, usize)) -> Vec<(usize, usize)> {
+    let a = Point::from_cell(a);
+    let d = Point::from_cell(b).checked_sub(&a).expect("Map too large");
 
-                    let c_point = (x as usize, y as usize);
+    walk(input, &a, &d, 2)
+}
 
-                    // Check if the point is within bounds and exists in the current positions
-                    if c_point.0 < input.nrows && c_point.1 < input.ncols {
-                        counted.insert(c_point);
-                    } else {
-                        // If the point is out of bounds, we can stop checking further multiples
-                        break;
-                    }
-                }
+/// Every grid point on the line through `a` and `b`, found by stepping from
+/// `a` by the gcd-reduced offset in both directions.
+fn lattice_line(input: &AntennaMap, a: (usize, usize), b: (usize, usize)) -> Vec<(usize, usize)> {
+    let a = Point::from_cell(a);
+    let step = Point::from_cell(b)
+        .checked_sub(&a)
+        .expect("Map too large")
+        .reduced();
+
+    // `a` itself is visited walking forwards
+    let mut points = walk(input, &a, &step, 0);
+    points.extend(walk(input, &a, &-step, 1));
+    points
+}
+
+/// Antinodes produced by resonant harmonics of one frequency, without the
+/// antennas themselves.
+fn resonant_antinodes(
+    input: &AntennaMap,
+    positions: &[(usize, usize)],
+    mode: LineMode,
+) -> HashSet<(usize, usize)> {
+    let mut counted = HashSet::new();
+
+    let len = positions.len();
+    for i in 0..len {
+        for j in 0..len {
+            if i == j {
+                continue;
             }
+            let a = positions[i];
+            let b = positions[j];
+            let line = match mode {
+                LineMode::Puzzle => puzzle_line(input, a, b),
+                LineMode::Lattice => lattice_line(input, a, b),
+            };
+            counted.extend(line);
         }
     }
 
+    counted
+}
+
+/// Resonant antinodes of every frequency, without the antennas themselves.
+fn all_resonant_antinodes(input: &AntennaMap, mode: LineMode) -> HashSet<(usize, usize)> {
+    input
+        .positions
+        .values()
+        .flat_map(|positions| resonant_antinodes(input, positions, mode))
+        .collect()
+}
+
+/// Part 2 antinodes per frequency. Any antenna sharing its frequency with
+/// another one lies on their line, so it counts as an antinode of its own
+/// frequency.
+fn part2_by_frequency(input: &AntennaMap, mode: LineMode) -> FrequencyAntinodes {
+    input
+        .positions
+        .iter()
+        .map(|(&c, positions)| {
+            let mut counted = resonant_antinodes(input, positions, mode);
+            if positions.len() > 1 {
+                counted.extend(positions.iter().copied());
+            }
+            (c, counted)
+        })
+        .collect()
+}
+
+fn all_antennas(input: &AntennaMap) -> HashSet<(usize, usize)> {
+    input.positions.values().flatten().copied().collect()
+}
+
+fn part2(input: &AntennaMap, mode: LineMode) -> usize {
+    let counted = all_resonant_antinodes(input, mode);
+
     // Collect all antenna positions into a set
-    let all_antennas: HashSet<(usize, usize)> = input.positions.values().flatten().copied().collect();
+    let all_antennas = all_antennas(input);
 
     // Calculate the number of counted points that are also antenna positions
     let overlap = counted.iter().filter(|p| all_antennas.contains(p)).count();
@@ -127,13 +445,972 @@ fn part2(input: &AntennaMap) -> usize {
     counted.len() - overlap + all_antennas.len()
 }
 
+/// Shows the grid points that only the lattice walk finds, i.e. the ones the
+/// puzzle mode skips over between antennas whose offset has a common factor.
+fn compare_line_modes(input: &AntennaMap) {
+    let antennas = all_antennas(input);
+    let puzzle: HashSet<_> = all_resonant_antinodes(input, LineMode::Puzzle)
+        .union(&antennas)
+        .copied()
+        .collect();
+    let lattice: HashSet<_> = all_resonant_antinodes(input, LineMode::Lattice)
+        .union(&antennas)
+        .copied()
+        .collect();
+
+    let mut extra: Vec<_> = lattice.difference(&puzzle).copied().collect();
+    extra.sort_unstable();
+    println!(
+        "Puzzle mode: {}, lattice mode: {}, only on the lattice: {}",
+        puzzle.len(),
+        lattice.len(),
+        extra.len()
+    );
+    for (row, col) in extra {
+        println!("  ({}, {})", row, col);
+    }
+
+    // Every puzzle harmonic is a multiple of the reduced step too
+    let missing = puzzle.difference(&lattice).count();
+    if missing > 0 {
+        println!(
+            "{} puzzle antinodes are missing from the lattice walk",
+            missing
+        );
+    }
+}
+
+/// Lists what every frequency contributes and which antinodes it shares with
+/// other frequencies, then draws the map with antinodes as `#`.
+fn report(label: &str, input: &AntennaMap, by_frequency: &FrequencyAntinodes) {
+    let mut owners: HashMap<(usize, usize), usize> = HashMap::new();
+    for point in by_frequency.values().flatten() {
+        *owners.entry(*point).or_default() += 1;
+    }
+
+    println!("{}:", label);
+    println!("  freq  antennas  pairs  antinodes  shared");
+    let mut frequencies: Vec<&char> = input.positions.keys().collect();
+    frequencies.sort_unstable();
+    for c in frequencies {
+        let antennas = input.positions[c].len();
+        let antinodes = &by_frequency[c];
+        let shared = antinodes.iter().filter(|p| owners[p] > 1).count();
+        println!(
+            "  {:>4}  {:>8}  {:>5}  {:>9}  {:>6}",
+            c,
+            antennas,
+            antennas * antennas.saturating_sub(1) / 2,
+            antinodes.len(),
+            shared
+        );
+    }
+    let shared = owners.values().filter(|&&n| n > 1).count();
+    println!(
+        "  total distinct antinodes: {}, shared by several frequencies: {}",
+        owners.len(),
+        shared
+    );
+
+    let mut grid = vec![vec!['.'; input.ncols]; input.nrows];
+    for (&c, positions) in &input.positions {
+        for &(row, col) in positions {
+            grid[row][col] = c;
+        }
+    }
+    for &(row, col) in owners.keys() {
+        grid[row][col] = '#';
+    }
+    for row in grid {
+        println!("  {}", row.into_iter().collect::<String>());
+    }
+}
+
+/// Checks the overlap subtraction at the end of `part2` against the plain
+/// union of the per-frequency antinodes.
+fn check_part2(input: &AntennaMap, mode: LineMode, by_frequency: &FrequencyAntinodes) {
+    let union: HashSet<_> = by_frequency.values().flatten().collect();
+    let result = part2(input, mode);
+    if union.len() == result {
+        println!(
+            "Part 2 overlap subtraction agrees with the union: {}",
+            result
+        );
+        return;
+    }
+
+    // `part2` adds every antenna, even those with no partner to resonate with
+    let mut lone: Vec<_> = input
+        .positions
+        .iter()
+        .filter(|(_, positions)| positions.len() == 1)
+        .map(|(&c, positions)| (c, positions[0]))
+        .filter(|(_, p)| !union.contains(p))
+        .collect();
+    lone.sort_unstable();
+    println!(
+        "Part 2 counts {} but the per-frequency union has {}; unpaired antennas off every line: {:?}",
+        result,
+        union.len(),
+        lone
+    );
+}
+
+/// The inclusive box antinodes of a sparse field are searched in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    min: Point,
+    max: Point,
+}
+
+impl FromStr for Bounds {
+    type Err = String;
+
+    /// Parses `x0..=x1,y0..=y1[,z0..=z1]`; a missing `z` range is `0..=0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges: Vec<&str> = s.split(',').collect();
+        if ranges.len() != 2 && ranges.len() != 3 {
+            return Err(format!("Bounds need two or three ranges: {}", s));
+        }
+
+        let (mut min, mut max) = ([0; 3], [0; 3]);
+        for (axis, range) in ranges.iter().enumerate() {
+            let (lo, hi) = range
+                .split_once("..=")
+                .ok_or_else(|| format!("Range must look like 0..=99, got {}", range))?;
+            min[axis] = lo
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid range: {}", range))?;
+            max[axis] = hi
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid range: {}", range))?;
+            if min[axis] > max[axis] {
+                return Err(format!("Empty range: {}", range));
+            }
+        }
+        Ok(Bounds {
+            min: Point::from_coords(min),
+            max: Point::from_coords(max),
+        })
+    }
+}
+
+impl Bounds {
+    /// The smallest box holding all `points`.
+    fn around<'a>(points: impl IntoIterator<Item = &'a Point>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = *points.next()?;
+        Some(points.fold(
+            Bounds {
+                min: first,
+                max: first,
+            },
+            |bounds, p| Bounds {
+                min: Point {
+                    x: bounds.min.x.min(p.x),
+                    y: bounds.min.y.min(p.y),
+                    z: bounds.min.z.min(p.z),
+                },
+                max: Point {
+                    x: bounds.max.x.max(p.x),
+                    y: bounds.max.y.max(p.y),
+                    z: bounds.max.z.max(p.z),
+                },
+            },
+        ))
+    }
+
+    fn contains(&self, p: &Point) -> bool {
+        (self.min.x..=self.max.x).contains(&p.x)
+            && (self.min.y..=self.max.y).contains(&p.y)
+            && (self.min.z..=self.max.z).contains(&p.z)
+    }
+
+    /// The range of `k` for which `p + k * step` lies inside the box.
+    fn steps(&self, p: &Point, step: &Vector) -> Option<(i128, i128)> {
+        let (mut lo, mut hi) = (i128::MIN, i128::MAX);
+        let below = self.min.wide_sub(p);
+        let above = self.max.wide_sub(p);
+        for ((below, above), step) in below.into_iter().zip(above).zip(step.wide()) {
+            if step == 0 {
+                if below > 0 || above < 0 {
+                    return None;
+                }
+                continue;
+            }
+            let (from, to) = if step > 0 {
+                (below, above)
+            } else {
+                (above, below)
+            };
+            lo = lo.max(Integer::div_ceil(&from, &step));
+            hi = hi.min(Integer::div_floor(&to, &step));
+        }
+
+        // A zero step would never leave the box
+        if lo > hi || lo == i128::MIN {
+            return None;
+        }
+        Some((lo, hi))
+    }
+}
+
+/// Antennas given as a list of coordinates rather than a dense map, so the
+/// field can be huge or three dimensional.
+struct AntennaField {
+    bounds: Bounds,
+    positions: HashMap<char, Vec<Point>>,
+}
+
+impl AntennaField {
+    /// The sparse view of a dense map, with rows as `x` and columns as `y`.
+    /// An empty map gets a box whose `max` lies below its `min`, which holds
+    /// no points at all.
+    fn from_map(input: &AntennaMap) -> Self {
+        let max = match (input.nrows.checked_sub(1), input.ncols.checked_sub(1)) {
+            (Some(row), Some(col)) => Point::from_cell((row, col)),
+            _ => Point { x: -1, y: -1, z: 0 },
+        };
+        AntennaField {
+            bounds: Bounds {
+                min: Point::from_cell((0, 0)),
+                max,
+            },
+            positions: input
+                .positions
+                .iter()
+                .map(|(&c, positions)| {
+                    (c, positions.iter().copied().map(Point::from_cell).collect())
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Parses `<frequency> x,y[,z]`.
+fn parse_antenna(line: &str) -> Option<(char, Point)> {
+    let (frequency, coords) = line.split_once(char::is_whitespace)?;
+    let mut chars = frequency.chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) if is_antenna(c) => c,
+        _ => return None,
+    };
+    let coords = coords
+        .split(',')
+        .map(|n| n.trim().parse().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    match coords[..] {
+        [x, y] => Some((c, Point { x, y, z: 0 })),
+        [x, y, z] => Some((c, Point { x, y, z })),
+        _ => None,
+    }
+}
+
+/// Reads one antenna per line as `<frequency> x,y[,z]`. Without explicit
+/// `bounds` the box around the antennas is used. An antenna listed twice
+/// would pair with itself, so that is an error.
+fn read_sparse_input(path: &Path, bounds: Option<Bounds>) -> AntennaField {
+    let file = File::open(path).expect("Failed to open file");
+    let reader = BufReader::new(file);
+
+    let mut positions: HashMap<char, Vec<Point>> = HashMap::new();
+    let mut listed: HashMap<(char, Point), usize> = HashMap::new();
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line.expect("Failed to read line");
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (c, coord) = parse_antenna(line)
+            .unwrap_or_else(|| panic!("Invalid antenna on line {}: {}", line_no + 1, line));
+        if let Some(first) = listed.insert((c, coord), line_no + 1) {
+            panic!(
+                "Duplicate antenna on line {}: {} (already on line {})",
+                line_no + 1,
+                line,
+                first
+            );
+        }
+        positions.entry(c).or_default().push(coord);
+    }
+
+    let bounds = bounds
+        .or_else(|| Bounds::around(positions.values().flatten()))
+        .expect("No antennas and no bounds given");
+    AntennaField { bounds, positions }
+}
+
+/// All the lattice points of a line, stored with its direction reduced and
+/// pointing "forwards", and the base point closest to the origin along the
+/// first moving axis, so that equal lines compare equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Line {
+    base: Point,
+    step: Vector,
+}
+
+impl Line {
+    fn through(a: &Point, b: &Point) -> Self {
+        let mut step = b.checked_sub(a).expect("Coordinates too large").reduced();
+        let (axis, forward) = step
+            .components()
+            .into_iter()
+            .enumerate()
+            .find(|&(_, d)| d != 0)
+            .expect("Antennas must differ");
+        if forward < 0 {
+            step = -step;
+        }
+        let k = Integer::div_floor(&a.coords()[axis], &step.components()[axis]);
+        let base = a
+            .checked_offset(&step, -(k as i128))
+            .expect("Coordinates too large");
+        Line { base, step }
+    }
+
+    /// The `k` with `base + k * step == p`, for a point `p` on the line.
+    fn parameter(&self, p: &Point) -> i128 {
+        let axis = (0..3)
+            .find(|&axis| self.step.components()[axis] != 0)
+            .expect("Antennas must differ");
+        p.wide_sub(&self.base)[axis] / self.step.wide()[axis]
+    }
+
+    /// The points of the line the puzzle mode reaches from the pair `a`,
+    /// `b` on it: whole multiples of their raw offset away from `a`.
+    fn harmonics(&self, a: &Point, b: &Point) -> Progression {
+        Progression::new(
+            (self.parameter(b) - self.parameter(a)).abs(),
+            self.parameter(a),
+        )
+    }
+
+    /// The lattice point two distinct lines cross at, if any.
+    fn intersection(&self, other: &Line) -> Option<Point> {
+        let u = self.step.wide();
+        let v = other.step.wide();
+        let w = other.base.wide_sub(&self.base);
+        let n = cross(&u, &v);
+
+        // Parallel lines never meet, skew lines are not coplanar
+        if n == [0; 3] || dot(&w, &n) != 0 {
+            return None;
+        }
+
+        // base + k * u = other.base + j * v  =>  k (u x v) = w x v
+        let numerator = dot(&cross(&w, &v), &n);
+        let denominator = dot(&n, &n);
+        if numerator % denominator != 0 {
+            return None;
+        }
+        self.base
+            .checked_offset(&self.step, numerator / denominator)
+    }
+}
+
+/// The parameters `residue + j * modulus` of points on a line, see
+/// [`Line::parameter`]. Intersections can have huge moduli, so they are big
+/// integers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Progression {
+    modulus: BigInt,
+    /// Always in `0..modulus`.
+    residue: BigInt,
+}
+
+impl Progression {
+    fn new(modulus: i128, residue: i128) -> Self {
+        let modulus = BigInt::from(modulus);
+        Progression {
+            residue: BigInt::from(residue).mod_floor(&modulus),
+            modulus,
+        }
+    }
+
+    fn contains(&self, k: &BigInt) -> bool {
+        (k - &self.residue).is_multiple_of(&self.modulus)
+    }
+
+    /// Whether every point of `other` is also one of these.
+    fn covers(&self, other: &Progression) -> bool {
+        other.modulus.is_multiple_of(&self.modulus) && self.contains(&other.residue)
+    }
+
+    /// The number of points with `lo <= k <= hi`.
+    fn count_within(&self, lo: &BigInt, hi: &BigInt) -> BigInt {
+        let below = |k: &BigInt| (k - &self.residue).div_floor(&self.modulus);
+        below(hi) - below(&(lo - 1))
+    }
+
+    /// The points on both progressions, by the Chinese remainder theorem.
+    fn intersection(&self, other: &Progression) -> Option<Progression> {
+        let ExtendedGcd { gcd, x, .. } = self.modulus.extended_gcd(&other.modulus);
+        let gap = &other.residue - &self.residue;
+        if !gap.is_multiple_of(&gcd) {
+            return None;
+        }
+        // `x * self.modulus == gcd` modulo the other modulus
+        let modulus = &self.modulus / &gcd * &other.modulus;
+        let residue = (&self.residue + &self.modulus * (gap / &gcd * x)).mod_floor(&modulus);
+        Some(Progression { modulus, residue })
+    }
+}
+
+/// Unions of progressions with at most this many points, or repeating with
+/// at most this period, are counted point by point.
+const UNION_SCAN: usize = 1 << 16;
+
+/// How many points with `lo <= k <= hi` lie on at least one of the
+/// progressions. Each point is counted on the first progression it lies on,
+/// which takes away the union of that progression's intersections with the
+/// earlier ones. Those get sparse quickly, and once few points or a short
+/// period are left they are simply counted.
+fn union_within(progressions: &[Progression], lo: i128, hi: i128) -> BigInt {
+    let (lo_big, hi_big) = (BigInt::from(lo), BigInt::from(hi));
+
+    // Progressions inside another one add nothing
+    let mut sorted: Vec<&Progression> = progressions
+        .iter()
+        .filter(|p| p.count_within(&lo_big, &hi_big) > BigInt::zero())
+        .collect();
+    sorted.sort_by(|p, q| p.modulus.cmp(&q.modulus));
+    let mut kept: Vec<&Progression> = Vec::new();
+    for p in sorted {
+        if !kept.iter().any(|q| q.covers(p)) {
+            kept.push(p);
+        }
+    }
+
+    let points: BigInt = kept.iter().map(|p| p.count_within(&lo_big, &hi_big)).sum();
+    if points <= BigInt::from(UNION_SCAN) {
+        let mut seen = HashSet::new();
+        for p in &kept {
+            let mut k = &lo_big + (&p.residue - &lo_big).mod_floor(&p.modulus);
+            while k <= hi_big {
+                let next = &k + &p.modulus;
+                seen.insert(k);
+                k = next;
+            }
+        }
+        return BigInt::from(seen.len());
+    }
+
+    let period = kept
+        .iter()
+        .fold(BigInt::from(1), |period, p| period.lcm(&p.modulus));
+    if let Some(period) = usize::try_from(&period).ok().filter(|&n| n <= UNION_SCAN) {
+        let mut covered = vec![false; period];
+        for p in &kept {
+            let (modulus, residue) = (usize::try_from(&p.modulus), usize::try_from(&p.residue));
+            let (Ok(modulus), Ok(residue)) = (modulus, residue) else {
+                unreachable!("Moduli divide the period");
+            };
+            for k in (residue..period).step_by(modulus) {
+                covered[k] = true;
+            }
+        }
+        let once = covered.iter().filter(|&&c| c).count();
+        let len = hi - lo + 1;
+        let (repeats, rest) = (len / period as i128, len % period as i128);
+        let start = lo.rem_euclid(period as i128) as usize;
+        let partial = (0..rest as usize)
+            .filter(|&k| covered[(start + k) % period])
+            .count();
+        return BigInt::from(repeats) * once + partial;
+    }
+
+    let mut total = BigInt::zero();
+    for (i, p) in kept.iter().enumerate() {
+        let earlier: Vec<Progression> =
+            kept[..i].iter().filter_map(|q| p.intersection(q)).collect();
+        total += p.count_within(&lo_big, &hi_big) - union_within(&earlier, lo, hi);
+    }
+    total
+}
+
+/// Part 1 on a sparse field, counting antinodes inside its bounds.
+fn sparse_part1(field: &AntennaField, rule: &AntinodeRule) -> usize {
+    let offsets: Vec<(i64, i64)> = rule
+        .ratios
+        .iter()
+        .flat_map(|ratio| ratio.offsets(rule.internal))
+        .collect();
+    let mut counted = HashSet::new();
+
+    for positions in field.positions.values() {
+        for a in positions {
+            for b in positions {
+                if a == b {
+                    continue;
+                }
+                let d = b.checked_sub(a).expect("Coordinates too large");
+                for &(num, den) in &offsets {
+                    // Only whole lattice points can hold an antinode
+                    if let Some(point) = d.scaled(num, den).and_then(|v| a.checked_offset(&v, 1))
+                        && field.bounds.contains(&point)
+                    {
+                        counted.insert(point);
+                    }
+                }
+            }
+        }
+    }
+
+    counted.len()
+}
+
+/// Part 2 on a sparse field. Paired antennas count as antinodes of their
+/// own lines, unpaired ones do not.
+///
+/// No line is walked: every distinct line adds up the points its pairs
+/// reach inside the bounds, and the points where lines cross are taken off
+/// for every extra line reaching them, so the extent of the field does not
+/// matter. In lattice mode a pair reaches every point of its line, in
+/// puzzle mode only those a whole number of raw offsets away.
+fn sparse_part2(field: &AntennaField, mode: LineMode) -> u64 {
+    let mut lines: HashMap<Line, Vec<Progression>> = HashMap::new();
+    for positions in field.positions.values() {
+        for (i, a) in positions.iter().enumerate() {
+            for b in &positions[i + 1..] {
+                let line = Line::through(a, b);
+                let reached = match mode {
+                    LineMode::Puzzle => line.harmonics(a, b),
+                    LineMode::Lattice => Progression::new(1, 0),
+                };
+                lines.entry(line).or_default().push(reached);
+            }
+        }
+    }
+    let lines: Vec<(Line, Vec<Progression>)> = lines.into_iter().collect();
+
+    let mut total = BigInt::zero();
+    for (line, reached) in &lines {
+        if let Some((lo, hi)) = field.bounds.steps(&line.base, &line.step) {
+            total += union_within(reached, lo, hi);
+        }
+    }
+
+    // A point reached along `m` lines was counted `m` times
+    let mut crossings: HashMap<Point, HashSet<usize>> = HashMap::new();
+    for (i, (line, _)) in lines.iter().enumerate() {
+        for (j, (other, _)) in lines.iter().enumerate().skip(i + 1) {
+            if let Some(point) = line.intersection(other)
+                && field.bounds.contains(&point)
+            {
+                crossings.entry(point).or_default().extend([i, j]);
+            }
+        }
+    }
+    for (point, on) in crossings {
+        let reaching = on
+            .into_iter()
+            .filter(|&i| {
+                let (line, reached) = &lines[i];
+                let k = BigInt::from(line.parameter(&point));
+                reached.iter().any(|p| p.contains(&k))
+            })
+            .count();
+        total -= reaching.saturating_sub(1);
+    }
+
+    u64::try_from(total).expect("Too many antinodes to count")
+}
+
+fn run_sparse(field: &AntennaField, rule: &AntinodeRule, mode: LineMode) {
+    let antennas: usize = field.positions.values().map(Vec::len).sum();
+    println!(
+        "Sparse field: {} antennas of {} frequencies within {:?}..={:?}",
+        antennas,
+        field.positions.len(),
+        field.bounds.min.coords(),
+        field.bounds.max.coords()
+    );
+
+    let result = sparse_part1(field, rule);
+    println!("Part 1 Solution: {}", result);
+
+    let result = sparse_part2(field, mode);
+    println!("Part 2 Solution: {}", result);
+}
+
+/// Checks the sparse computations against the dense ones on the same map.
+fn check_sparse(input: &AntennaMap, rule: &AntinodeRule) {
+    let field = AntennaField::from_map(input);
+    let mut ok = true;
+
+    let (dense, sparse) = (part1(input, rule), sparse_part1(&field, rule));
+    if dense != sparse {
+        println!("Part 1 mismatch: dense {}, sparse {}", dense, sparse);
+        ok = false;
+    }
+
+    for mode in [LineMode::Puzzle, LineMode::Lattice] {
+        // Compare against the union, which leaves out unpaired antennas
+        let dense = part2_by_frequency(input, mode)
+            .into_values()
+            .flatten()
+            .collect::<HashSet<_>>()
+            .len() as u64;
+        let sparse = sparse_part2(&field, mode);
+        if dense != sparse {
+            println!(
+                "Part 2 {:?} mismatch: dense {}, sparse {}",
+                mode, dense, sparse
+            );
+            ok = false;
+        }
+    }
+
+    if ok {
+        println!("Sparse and dense antinode counts agree");
+    }
+}
+
+/// Whether `c` is an antinode of `a` and `b` under `rule`, straight from the
+/// definition: on their line, with the distances to them in one of the
+/// ratios, and outside the pair unless internal antinodes are allowed.
+fn is_ratio_antinode(c: &Point, a: &Point, b: &Point, rule: &AntinodeRule) -> bool {
+    let (ca, cb) = (a.wide_sub(c), b.wide_sub(c));
+    if cross(&ca, &cb) != [0; 3] {
+        return false;
+    }
+    let (da, db) = (dot(&ca, &ca), dot(&cb, &cb));
+    let between = dot(&ca, &cb) < 0;
+    rule.ratios.iter().any(|ratio| {
+        let (near, far) = (ratio.near as i128, ratio.far as i128);
+        let in_ratio = far * far * db == near * near * da || far * far * da == near * near * db;
+        in_ratio && (rule.internal || !between)
+    })
+}
+
+/// Whether `c` lies on the line through `a` and `b` and, in puzzle mode, a
+/// whole number of antenna offsets away from `a`.
+fn is_resonant(c: &Point, a: &Point, b: &Point, mode: LineMode) -> bool {
+    let (ac, ab) = (c.wide_sub(a), b.wide_sub(a));
+    if cross(&ac, &ab) != [0; 3] {
+        return false;
+    }
+    match mode {
+        LineMode::Lattice => true,
+        LineMode::Puzzle => (0..3)
+            .find(|&axis| ab[axis] != 0)
+            .is_some_and(|axis| ac[axis] % ab[axis] == 0),
+    }
+}
+
+/// Checks every cell of the map against the definitions and collects the
+/// antinodes of part 1 and part 2.
+fn brute_force(input: &AntennaMap, rule: &AntinodeRule, mode: LineMode) -> (Cells, Cells) {
+    let pairs: Vec<(Point, Point)> = input
+        .positions
+        .values()
+        .flat_map(|positions| {
+            positions.iter().enumerate().flat_map(move |(i, &a)| {
+                positions[i + 1..]
+                    .iter()
+                    .map(move |&b| (Point::from_cell(a), Point::from_cell(b)))
+            })
+        })
+        .collect();
+
+    let (mut part1, mut part2) = (HashSet::new(), HashSet::new());
+    for row in 0..input.nrows {
+        for col in 0..input.ncols {
+            let c = Point::from_cell((row, col));
+            if pairs.iter().any(|(a, b)| is_ratio_antinode(&c, a, b, rule)) {
+                part1.insert((row, col));
+            }
+            if pairs.iter().any(|(a, b)| is_resonant(&c, a, b, mode)) {
+                part2.insert((row, col));
+            }
+        }
+    }
+    (part1, part2)
+}
+
+/// Compares the antinode computations, dense and sparse, with a brute force
+/// check of every cell.
+fn verify_map(input: &AntennaMap, rule: &AntinodeRule) -> bool {
+    let field = AntennaField::from_map(input);
+    let mut ok = true;
+
+    for mode in [LineMode::Puzzle, LineMode::Lattice] {
+        let (part1, part2) = brute_force(input, rule, mode);
+
+        let found: HashSet<_> = part1_by_frequency(input, rule)
+            .into_values()
+            .flatten()
+            .collect();
+        if found != part1 || sparse_part1(&field, rule) != part1.len() {
+            println!(
+                "Part 1 mismatch on {}x{} map: brute force {}, found {}",
+                input.nrows,
+                input.ncols,
+                part1.len(),
+                found.len()
+            );
+            ok = false;
+        }
+
+        let found: HashSet<_> = part2_by_frequency(input, mode)
+            .into_values()
+            .flatten()
+            .collect();
+        if found != part2 || sparse_part2(&field, mode) != part2.len() as u64 {
+            println!(
+                "Part 2 {:?} mismatch on {}x{} map: brute force {}, found {}",
+                mode,
+                input.nrows,
+                input.ncols,
+                part2.len(),
+                found.len()
+            );
+            ok = false;
+        }
+    }
+
+    ok
+}
+
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|pos| args.get(pos + 1))
+        .map(String::as_str)
+}
+
 fn main() {
     println!("--- Day 8: Resonant Collinearity ---");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `--lines puzzle|lattice` picks how harmonics walk the antenna lines
+    let mode = arg_value(&args, "--lines")
+        .map_or(Ok(LineMode::Puzzle), LineMode::from_str)
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    // `--ratios 1:2,1:3` and `--internal` change which points part 1 counts
+    let ratios = arg_value(&args, "--ratios").map(|list| {
+        list.split(',')
+            .map(Ratio::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| panic!("{}", e))
+    });
+    let default_rule = AntinodeRule::default();
+    let rule = AntinodeRule {
+        ratios: ratios.unwrap_or(default_rule.ratios),
+        internal: args.iter().any(|a| a == "--internal"),
+    };
+
+    // `--sparse PATH` reads a coordinate list instead of the map, searching
+    // antinodes within `--bounds x0..=x1,y0..=y1[,z0..=z1]`
+    if let Some(path) = arg_value(&args, "--sparse") {
+        let bounds = arg_value(&args, "--bounds")
+            .map(|b| Bounds::from_str(b).unwrap_or_else(|e| panic!("{}", e)));
+        run_sparse(&read_sparse_input(Path::new(path), bounds), &rule, mode);
+        return;
+    }
+
     let input = read_input();
 
-    let result = part1(&input);
+    let result = part1(&input, &rule);
     println!("Part 1 Solution: {}", result);
 
-    let result = part2(&input);
+    let result = part2(&input, mode);
     println!("Part 2 Solution: {}", result);
+
+    // `--report` breaks both parts down by frequency
+    if args.iter().any(|a| a == "--report") {
+        report(
+            "Part 1 antinodes",
+            &input,
+            &part1_by_frequency(&input, &rule),
+        );
+        let by_frequency = part2_by_frequency(&input, mode);
+        report("Part 2 antinodes", &input, &by_frequency);
+        check_part2(&input, mode, &by_frequency);
+    }
+
+    // `--check-sparse` runs the map through the sparse representation too
+    if args.iter().any(|a| a == "--check-sparse") {
+        check_sparse(&input, &rule);
+    }
+
+    // `--verify` checks the input cell by cell
+    if args.iter().any(|a| a == "--verify") && verify_map(&input, &rule) {
+        println!("Antinodes agree with the brute force check");
+    }
+
+    // `--compare-lines` lists the points only the lattice walk reaches
+    if args.iter().any(|a| a == "--compare-lines") {
+        compare_line_modes(&input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small xorshift generator, enough to draw random maps.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn random_map(rng: &mut Rng) -> AntennaMap {
+        let nrows = 1 + rng.below(20);
+        let ncols = 1 + rng.below(20);
+        let frequencies = ['a', 'A', '0'];
+
+        let mut cells = HashMap::new();
+        for _ in 0..rng.below(12) {
+            let cell = (rng.below(nrows), rng.below(ncols));
+            cells.insert(cell, frequencies[rng.below(frequencies.len())]);
+        }
+
+        let mut positions: HashMap<char, Vec<(usize, usize)>> = HashMap::new();
+        for (cell, c) in cells {
+            positions.entry(c).or_default().push(cell);
+        }
+        AntennaMap {
+            nrows,
+            ncols,
+            positions,
+        }
+    }
+
+    #[test]
+    fn antinodes_match_brute_force_on_random_maps() {
+        let rules = [
+            AntinodeRule::default(),
+            AntinodeRule {
+                ratios: vec![Ratio { near: 1, far: 2 }, Ratio { near: 1, far: 3 }],
+                internal: true,
+            },
+        ];
+        let mut rng = Rng(0x2024_0008);
+        for _ in 0..200 {
+            let map = random_map(&mut rng);
+            for rule in &rules {
+                assert!(verify_map(&map, rule));
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_part2_matches_brute_force_in_3d() {
+        let mut rng = Rng(0x2024_0039);
+        for _ in 0..300 {
+            let max = [0; 3].map(|_| rng.below(8) as i64);
+            let bounds = Bounds {
+                min: Point::from_coords([0; 3]),
+                max: Point::from_coords(max),
+            };
+            let mut positions: HashMap<char, Vec<Point>> = HashMap::new();
+            for _ in 0..rng.below(8) {
+                let p = Point::from_coords(max.map(|m| rng.below(m as usize + 3) as i64 - 1));
+                let c = ['a', 'b'][rng.below(2)];
+                if !positions.values().flatten().any(|q| *q == p) {
+                    positions.entry(c).or_default().push(p);
+                }
+            }
+            let field = AntennaField { bounds, positions };
+
+            for mode in [LineMode::Puzzle, LineMode::Lattice] {
+                let mut expected = 0;
+                for x in 0..=max[0] {
+                    for y in 0..=max[1] {
+                        for z in 0..=max[2] {
+                            let c = Point { x, y, z };
+                            let reached = field.positions.values().any(|positions| {
+                                positions.iter().enumerate().any(|(i, a)| {
+                                    positions[i + 1..]
+                                        .iter()
+                                        .any(|b| is_resonant(&c, a, b, mode))
+                                })
+                            });
+                            expected += reached as u64;
+                        }
+                    }
+                }
+                assert_eq!(sparse_part2(&field, mode), expected, "{:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn progression_unions_match_counting() {
+        let mut rng = Rng(0x2024_1008);
+        for _ in 0..2000 {
+            let progressions: Vec<Progression> = (0..1 + rng.below(6))
+                .map(|_| Progression::new(1 + rng.below(12) as i128, rng.below(40) as i128 - 20))
+                .collect();
+            let lo = rng.below(60) as i128 - 30;
+            let hi = lo + rng.below(60) as i128;
+            let expected = (lo..=hi)
+                .filter(|&k| progressions.iter().any(|p| p.contains(&BigInt::from(k))))
+                .count();
+            assert_eq!(
+                union_within(&progressions, lo, hi),
+                BigInt::from(expected),
+                "{:?} {}..={}",
+                progressions,
+                lo,
+                hi
+            );
+        }
+    }
+
+    #[test]
+    fn lattice_mode_finds_points_between_antennas() {
+        let (a, b) = ((0, 0), (2, 4));
+        let map = AntennaMap {
+            nrows: 5,
+            ncols: 5,
+            positions: HashMap::from([('a', vec![a, b])]),
+        };
+
+        assert!(lattice_line(&map, a, b).contains(&(1, 2)));
+        assert!(!puzzle_line(&map, a, b).contains(&(1, 2)));
+        assert!(!puzzle_line(&map, b, a).contains(&(1, 2)));
+
+        assert_eq!(part2(&map, LineMode::Puzzle), 2);
+        assert_eq!(part2(&map, LineMode::Lattice), 3);
+    }
+
+    #[test]
+    fn empty_maps_have_no_antinodes() {
+        let rule = AntinodeRule::default();
+        for (nrows, ncols) in [(0, 0), (3, 0)] {
+            let map = AntennaMap {
+                nrows,
+                ncols,
+                positions: HashMap::new(),
+            };
+            let field = AntennaField::from_map(&map);
+            assert_eq!(sparse_part1(&field, &rule), 0);
+            assert_eq!(sparse_part2(&field, LineMode::Puzzle), 0);
+            assert!(verify_map(&map, &rule));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate antenna on line 3: a 2,1 (already on line 1)")]
+    fn sparse_input_rejects_repeated_antennas() {
+        let path = std::env::temp_dir().join(format!("day8-duplicates-{}.txt", std::process::id()));
+        std::fs::write(&path, "a 2,1\nb 2,1\na 2,1\n").expect("Failed to write file");
+        let field = std::panic::catch_unwind(|| read_sparse_input(&path, None));
+        std::fs::remove_file(&path).expect("Failed to remove file");
+        if let Err(panic) = field {
+            std::panic::resume_unwind(panic);
+        }
+    }
 }
@@ -1,44 +1,1377 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
 
-fn read_input() -> Vec<char> {
-    let path = Path::new("input/day9.txt");
+fn read_input(path: &str) -> String {
+    fs::read_to_string(path).expect("Failed to open file")
+}
 
-    let file = File::open(&path).expect("Failed to open file");
-    let reader = BufReader::new(file);
+/// How a disk map is written down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Single digit lengths, possibly wrapped over several lines.
+    Dense,
+    /// Comma or newline separated lengths of any size.
+    Csv,
+    /// An explicit `{"size": .., "files": [{"id", "start", "len"}]}` layout.
+    Json,
+}
 
-    let line = reader
-        .lines()
-        .next()
-        .expect("No lines found")
-        .expect("Failed to read line");
-    line.chars().collect()
+impl Format {
+    /// Guesses the format of a disk map. Several lines of digits without a
+    /// comma read differently as a wrapped dense map and as one length per
+    /// line, so unless every line is a single digit they are reported as
+    /// ambiguous instead.
+    fn detect(text: &str) -> Result<Self, String> {
+        let text = text.trim_start();
+        if text.starts_with('{') {
+            return Ok(Format::Json);
+        }
+        if text.contains(',') {
+            return Ok(Format::Csv);
+        }
+
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.len() > 1 && lines.iter().any(|line| line.len() > 1) {
+            return Err(
+                "Ambiguous disk map: give --format dense for a wrapped map or --format csv \
+                 for one length per line"
+                    .to_string(),
+            );
+        }
+        Ok(Format::Dense)
+    }
 }
 
-#[derive(Clone, Debug)]
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dense" => Ok(Format::Dense),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown format: {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonLayout {
+    size: usize,
+    files: Vec<FileSpan>,
+}
+
+/// Single digit lengths; line breaks are ignored so long maps may wrap.
+fn parse_dense(text: &str) -> Result<Vec<usize>, String> {
+    let mut lengths = Vec::new();
+    for (row, line) in text.lines().enumerate() {
+        for (col, c) in line.trim_end().chars().enumerate() {
+            let len = c.to_digit(10).ok_or_else(|| {
+                format!(
+                    "Invalid length {:?} at line {}, column {}",
+                    c,
+                    row + 1,
+                    col + 1
+                )
+            })?;
+            lengths.push(len as usize);
+        }
+    }
+    Ok(lengths)
+}
+
+/// Lengths separated by commas or line breaks; a trailing comma is allowed.
+fn parse_csv(text: &str) -> Result<Vec<usize>, String> {
+    let mut lengths = Vec::new();
+    for (row, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let line = line.strip_suffix(',').unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        for (entry, field) in line.split(',').enumerate() {
+            let len = field.trim().parse().map_err(|_| {
+                format!(
+                    "Invalid length {:?} at line {}, entry {}",
+                    field,
+                    row + 1,
+                    entry + 1
+                )
+            })?;
+            lengths.push(len);
+        }
+    }
+    Ok(lengths)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 enum FileBlock {
     FileId(usize),
     FreeSpace,
 }
 
-fn part1(input: &Vec<char>) -> usize {
-    let mut file_blocks = Vec::new();
-    let mut file_id = 0;
-    let mut free_space = false;
+/// A contiguous run of blocks holding (part of) one file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileSpan {
+    id: usize,
+    start: usize,
+    len: usize,
+}
+
+impl FileSpan {
+    /// `id * (start + ... + start + len - 1)` as an arithmetic series, so
+    /// long spans cost no more than short ones.
+    fn checksum(&self) -> Option<u128> {
+        let (start, len) = (self.start as u128, self.len as u128);
+        // Neither product can overflow as both factors come from a usize
+        let positions = (start * len).checked_add(len * len.saturating_sub(1) / 2)?;
+        positions.checked_mul(self.id as u128)
+    }
+}
+
+/// A contiguous run of free blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FreeSpan {
+    start: usize,
+    len: usize,
+}
+
+/// The integer type checksums are accumulated in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Width {
+    Usize,
+    U128,
+}
+
+/// The disk as spans rather than blocks, so its size does not matter.
+#[derive(Clone, Debug)]
+struct Disk {
+    size: usize,
+    /// File spans ordered by start, which `holes`, `runs` and the disk
+    /// images rely on, so build disks through `from_files`. A fragmented
+    /// file has several.
+    files: Vec<FileSpan>,
+    /// Free spans ordered by start, never empty or adjacent to each other.
+    free: Vec<FreeSpan>,
+}
+
+impl Disk {
+    fn parse(text: &str, format: Format) -> Result<Self, String> {
+        match format {
+            Format::Dense => Disk::from_lengths(&parse_dense(text)?),
+            Format::Csv => Disk::from_lengths(&parse_csv(text)?),
+            Format::Json => Disk::from_layout(
+                serde_json::from_str(text).map_err(|e| format!("Invalid JSON layout: {}", e))?,
+            ),
+        }
+    }
+
+    /// Alternating file and free space lengths, file ids counting up from 0.
+    fn from_lengths(lengths: &[usize]) -> Result<Self, String> {
+        if lengths.is_empty() {
+            return Err("Empty disk map".to_string());
+        }
+
+        let mut files = Vec::new();
+        let mut start: usize = 0;
+        for (i, &len) in lengths.iter().enumerate() {
+            if i % 2 == 0 && len > 0 {
+                files.push(FileSpan {
+                    id: i / 2,
+                    start,
+                    len,
+                });
+            }
+            start = start
+                .checked_add(len)
+                .ok_or_else(|| format!("Disk map too large at entry {}", i + 1))?;
+        }
+
+        Ok(Disk::from_files(files, start))
+    }
+
+    /// Checks that the files of a JSON layout fit on the disk without
+    /// overlapping. A file id may appear more than once for split files.
+    fn from_layout(layout: JsonLayout) -> Result<Self, String> {
+        let mut files = layout.files;
+        files.sort_unstable_by_key(|span| span.start);
+
+        let mut merged: Vec<FileSpan> = Vec::with_capacity(files.len());
+        let mut end = 0;
+        for span in files {
+            if span.len == 0 {
+                return Err(format!("File {} at {} has no blocks", span.id, span.start));
+            }
+            if span.start < end {
+                return Err(format!(
+                    "File {} at {} overlaps another file",
+                    span.id, span.start
+                ));
+            }
+            end = span
+                .start
+                .checked_add(span.len)
+                .filter(|&end| end <= layout.size)
+                .ok_or_else(|| {
+                    format!(
+                        "File {} at {} runs past the end of the {} block disk",
+                        span.id, span.start, layout.size
+                    )
+                })?;
 
-    for c in input {
-        let cdigit = c.to_digit(10).unwrap_or(0) as usize;
-        if free_space {
-            free_space = false;
-            file_blocks.extend(std::iter::repeat(FileBlock::FreeSpace).take(cdigit));
+            // Touching pieces of one file are a single span
+            match merged.last_mut() {
+                Some(last) if last.id == span.id && last.start + last.len == span.start => {
+                    last.len += span.len;
+                }
+                _ => merged.push(span),
+            }
+        }
+
+        Ok(Disk::from_files(merged, layout.size))
+    }
+
+    /// A disk of `size` blocks holding `files`, free everywhere else.
+    fn from_files(mut files: Vec<FileSpan>, size: usize) -> Self {
+        files.sort_unstable_by_key(|span| span.start);
+
+        let mut free = Vec::new();
+        let mut end = 0;
+        for span in &files {
+            if span.start > end {
+                free.push(FreeSpan {
+                    start: end,
+                    len: span.start - end,
+                });
+            }
+            end = span.start + span.len;
+        }
+        if size > end {
+            free.push(FreeSpan {
+                start: end,
+                len: size - end,
+            });
+        }
+
+        Disk { size, files, free }
+    }
+
+    /// The block by block view of the disk.
+    fn blocks(&self) -> Vec<FileBlock> {
+        let mut blocks = vec![FileBlock::FreeSpace; self.size];
+        for span in &self.files {
+            blocks[span.start..span.start + span.len].fill(FileBlock::FileId(span.id));
+        }
+        blocks
+    }
+
+    /// Sums the checksum span by span in `width`, failing instead of
+    /// wrapping when it does not fit.
+    fn checksum(&self, width: Width) -> Result<u128, String> {
+        let mut checksum: u128 = 0;
+        for span in &self.files {
+            checksum = span
+                .checksum()
+                .and_then(|sum| checksum.checked_add(sum))
+                .filter(|&sum| width == Width::U128 || usize::try_from(sum).is_ok())
+                .ok_or_else(|| match width {
+                    Width::Usize => {
+                        format!("Checksum overflows usize at file {} (try --wide)", span.id)
+                    }
+                    Width::U128 => format!("Checksum overflows u128 at file {}", span.id),
+                })?;
+        }
+        Ok(checksum)
+    }
+
+    /// Free spans with file blocks after them.
+    fn holes(&self) -> usize {
+        let last_file_end = self.files.last().map_or(0, |span| span.start + span.len);
+        self.free
+            .iter()
+            .filter(|span| span.start < last_file_end)
+            .count()
+    }
+
+    fn usage(&self) -> Usage {
+        let free: usize = self.free.iter().map(|span| span.len).sum();
+        let largest_free = self.free.iter().map(|span| span.len).max().unwrap_or(0);
+        Usage {
+            used: self.size - free,
+            free,
+            largest_free,
+            free_extents: self.free.len(),
+            // The share of free space outside the largest extent
+            fragmentation: if free == 0 {
+                0.0
+            } else {
+                1.0 - largest_free as f64 / free as f64
+            },
+        }
+    }
+
+    /// Files stored in more than one span.
+    fn split_files(&self) -> usize {
+        let mut ids: Vec<usize> = self.files.iter().map(|span| span.id).collect();
+        ids.sort_unstable();
+        ids.chunk_by(|a, b| a == b)
+            .filter(|spans| spans.len() > 1)
+            .count()
+    }
+
+    /// The file spans in disk order with touching pieces of one file joined,
+    /// so disks with the same blocks compare equal.
+    fn runs(&self) -> Vec<FileSpan> {
+        let mut runs: Vec<FileSpan> = Vec::with_capacity(self.files.len());
+        for &span in &self.files {
+            match runs.last_mut() {
+                Some(last) if last.id == span.id && last.start + last.len == span.start => {
+                    last.len += span.len;
+                }
+                _ => runs.push(span),
+            }
+        }
+        runs
+    }
+
+    fn from_blocks(blocks: &[FileBlock]) -> Self {
+        let mut files = Vec::new();
+        let mut start = 0;
+        for run in blocks.chunk_by(|a, b| a == b) {
+            if let FileBlock::FileId(id) = run[0] {
+                files.push(FileSpan {
+                    id,
+                    start,
+                    len: run.len(),
+                });
+            }
+            start += run.len();
+        }
+        Disk::from_files(files, blocks.len())
+    }
+}
+
+/// How the blocks of a disk are used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Usage {
+    used: usize,
+    free: usize,
+    largest_free: usize,
+    free_extents: usize,
+    /// 0 when all free space is one extent, approaching 1 as it scatters.
+    fragmentation: f64,
+}
+
+impl Usage {
+    fn print_header() {
+        println!(
+            "{:>10}  {:>9}  {:>9}  {:>8}  {:>8}  {:>6}",
+            "", "used", "free", "largest", "extents", "frag"
+        );
+    }
+
+    fn print_row(&self, label: &str) {
+        println!(
+            "{:>10}  {:>9}  {:>9}  {:>8}  {:>8}  {:>5.1}%",
+            label,
+            self.used,
+            self.free,
+            self.largest_free,
+            self.free_extents,
+            100.0 * self.fragmentation
+        );
+    }
+}
+
+/// How a whole file picks a free span to the left of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fit {
+    /// The leftmost span that fits.
+    First,
+    /// The smallest span that fits, leftmost among equals.
+    Best,
+    /// The largest span, leftmost among equals.
+    Worst,
+    /// The first span that fits after the previous move, wrapping around
+    /// to the start of the disk.
+    Next,
+}
+
+/// The free spans in disk order, indexed so every fit is found in
+/// logarithmic time however many distinct lengths there are: a segment tree
+/// of the longest span in each range of spans for first and next fit, and
+/// the spans ordered by length for best and worst fit.
+///
+/// Files are placed right to left, so a span at or after the file being
+/// placed can never be used again and is dropped from the index.
+struct FreeIndex {
+    spans: Vec<FreeSpan>,
+    /// `longest[1]` covers every span, and node `k` covers the ranges of
+    /// `2k` and `2k + 1`. Leaves are `leaves + i` for span `i`; dropped
+    /// and used up spans count as length 0.
+    longest: Vec<usize>,
+    leaves: usize,
+    by_len: BTreeSet<(usize, usize)>,
+    /// Spans from here on start at or after the last file placed.
+    live: usize,
+    /// Where the previous move ended, for next fit.
+    cursor: usize,
+}
+
+impl FreeIndex {
+    fn new(free: &[FreeSpan]) -> Self {
+        let leaves = free.len().next_power_of_two();
+        let mut longest = vec![0; 2 * leaves];
+        for (i, span) in free.iter().enumerate() {
+            longest[leaves + i] = span.len;
+        }
+        for k in (1..leaves).rev() {
+            longest[k] = longest[2 * k].max(longest[2 * k + 1]);
+        }
+        FreeIndex {
+            spans: free.to_vec(),
+            longest,
+            leaves,
+            by_len: free
+                .iter()
+                .enumerate()
+                .map(|(i, span)| (span.len, i))
+                .collect(),
+            live: free.len(),
+            cursor: 0,
+        }
+    }
+
+    fn set_len(&mut self, i: usize, len: usize) {
+        self.by_len.remove(&(self.spans[i].len, i));
+        if len > 0 {
+            self.by_len.insert((len, i));
+        }
+        self.spans[i].len = len;
+
+        let mut k = self.leaves + i;
+        self.longest[k] = len;
+        while k > 1 {
+            k /= 2;
+            self.longest[k] = self.longest[2 * k].max(self.longest[2 * k + 1]);
+        }
+    }
+
+    /// The first span from `from` on that holds `len` blocks, searching the
+    /// subtree of node `k` covering spans `lo..hi`.
+    fn leftmost(&self, k: usize, lo: usize, hi: usize, from: usize, len: usize) -> Option<usize> {
+        if hi <= from || self.longest[k] < len {
+            return None;
+        }
+        if hi - lo == 1 {
+            return Some(lo);
+        }
+        let mid = (lo + hi) / 2;
+        self.leftmost(2 * k, lo, mid, from, len)
+            .or_else(|| self.leftmost(2 * k + 1, mid, hi, from, len))
+    }
+
+    /// Takes `len` blocks from the free span `fit` picks among those before
+    /// `before`, returning where they start. `before` must not increase
+    /// from one call to the next.
+    fn take(&mut self, fit: Fit, len: usize, before: usize) -> Option<usize> {
+        while self.live > 0 && self.spans[self.live - 1].start >= before {
+            self.live -= 1;
+            self.set_len(self.live, 0);
+        }
+
+        let first = |from| self.leftmost(1, 0, self.leaves, from, len);
+        let i = match fit {
+            Fit::First => first(0),
+            Fit::Best => self.by_len.range((len, 0)..).next().map(|&(_, i)| i),
+            Fit::Worst => self
+                .by_len
+                .last()
+                .filter(|&&(longest, _)| longest >= len)
+                .and_then(|&(longest, _)| self.by_len.range((longest, 0)..).next())
+                .map(|&(_, i)| i),
+            Fit::Next => {
+                let from = self.spans.partition_point(|span| span.start < self.cursor);
+                first(from).or_else(|| first(0))
+            }
+        }?;
+
+        // The space left over after the file stays available
+        let start = self.spans[i].start;
+        self.spans[i].start += len;
+        self.set_len(i, self.spans[i].len - len);
+        self.cursor = start + len;
+        Some(start)
+    }
+}
+
+/// How the disk is compacted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    /// Block by block from the end of the disk into the leftmost free block.
+    Blocks,
+    /// Whole files, right to left by their first block, into a free span
+    /// picked by the fit. A split file moves as one and is joined.
+    Files(Fit),
+    /// Every file contiguous, moving as few blocks as a greedy search finds.
+    /// Files that are not split stay where they are.
+    Defragment,
+    /// Every file contiguous and all free space at the end.
+    Pack,
+}
+
+impl Strategy {
+    const ALL: [Strategy; 7] = [
+        Strategy::Blocks,
+        Strategy::Files(Fit::First),
+        Strategy::Files(Fit::Best),
+        Strategy::Files(Fit::Worst),
+        Strategy::Files(Fit::Next),
+        Strategy::Defragment,
+        Strategy::Pack,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Strategy::Blocks => "blocks",
+            Strategy::Files(Fit::First) => "first-fit",
+            Strategy::Files(Fit::Best) => "best-fit",
+            Strategy::Files(Fit::Worst) => "worst-fit",
+            Strategy::Files(Fit::Next) => "next-fit",
+            Strategy::Defragment => "defragment",
+            Strategy::Pack => "pack",
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Strategy::ALL
+            .into_iter()
+            .find(|strategy| strategy.name() == s)
+            .ok_or_else(|| format!("Unknown strategy: {}", s))
+    }
+}
+
+/// A span of blocks moving from one place to another. The source and the
+/// destination may overlap, as when a file slides left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Move {
+    id: usize,
+    from: usize,
+    to: usize,
+    len: usize,
+}
+
+impl Move {
+    /// The move as single blocks, last block first, the way the puzzle
+    /// moves blocks one at a time.
+    fn single_blocks(self) -> impl Iterator<Item = Move> {
+        (0..self.len).map(move |k| Move {
+            id: self.id,
+            from: self.from + self.len - 1 - k,
+            to: self.to + k,
+            len: 1,
+        })
+    }
+
+    /// The move that puts the blocks back.
+    fn reversed(self) -> Move {
+        Move {
+            from: self.to,
+            to: self.from,
+            ..self
+        }
+    }
+
+    /// Applies the move to the block view of a disk, checking that it picks
+    /// up the right file and only lands on free blocks.
+    fn apply(&self, blocks: &mut [FileBlock]) {
+        let source = &mut blocks[self.from..self.from + self.len];
+        assert!(
+            source.iter().all(|b| *b == FileBlock::FileId(self.id)),
+            "{:?} does not find its file at the source",
+            self
+        );
+        source.fill(FileBlock::FreeSpace);
+
+        let destination = &mut blocks[self.to..self.to + self.len];
+        assert!(
+            destination.iter().all(|b| *b == FileBlock::FreeSpace),
+            "{:?} lands on blocks in use",
+            self
+        );
+        destination.fill(FileBlock::FileId(self.id));
+    }
+}
+
+/// A compacted disk and the moves that produced it.
+struct Compaction {
+    disk: Disk,
+    moves: Vec<Move>,
+}
+
+impl Compaction {
+    fn blocks_moved(&self) -> usize {
+        self.moves.iter().map(|m| m.len).sum()
+    }
+}
+
+/// Fails only when defragmenting or packing a disk too full to join its
+/// split files.
+fn compact(disk: &Disk, strategy: Strategy) -> Result<Compaction, String> {
+    match strategy {
+        Strategy::Blocks => Ok(compact_blocks(disk)),
+        Strategy::Files(fit) => Ok(compact_files(disk, fit)),
+        Strategy::Defragment => defragment(disk),
+        Strategy::Pack => pack(disk),
+    }
+}
+
+/// Moves blocks one at a time from the end of the disk into the leftmost
+/// free block, working on whole spans at once.
+fn compact_blocks(disk: &Disk) -> Compaction {
+    let mut files = disk.files.clone();
+    files.sort_unstable_by_key(|span| span.start);
+    let mut free = disk.free.clone();
+
+    let mut moves = Vec::new();
+    let (mut i, mut j) = (0, files.len());
+    while i < free.len() && j > 0 {
+        let file = &mut files[j - 1];
+        let hole = &mut free[i];
+        // Only free space before the file is any use to it
+        if hole.start >= file.start {
+            break;
+        }
+
+        // The tail of the file fills the head of the hole
+        let len = hole.len.min(file.len);
+        file.len -= len;
+        moves.push(Move {
+            id: file.id,
+            from: file.start + file.len,
+            to: hole.start,
+            len,
+        });
+        hole.start += len;
+        hole.len -= len;
+
+        if file.len == 0 {
+            j -= 1;
+        }
+        if hole.len == 0 {
+            i += 1;
+        }
+    }
+
+    files.retain(|span| span.len > 0);
+    files.extend(moves.iter().map(|m| FileSpan {
+        id: m.id,
+        start: m.to,
+        len: m.len,
+    }));
+    // A tail that lands right before its own head joins it again
+    let compacted = Disk::from_files(files, disk.size);
+    Compaction {
+        disk: Disk::from_files(compacted.runs(), disk.size),
+        moves,
+    }
+}
+
+/// Moves whole files, the one whose first block is furthest right first,
+/// into the free span before them that `fit` picks. All the parts of a split
+/// file move together and come out joined.
+fn compact_files(disk: &Disk, fit: Fit) -> Compaction {
+    // Right to left is decreasing id order for dense maps, and gives layouts
+    // whose ids are not in disk order a rule too. Space freed by a move then
+    // lies right of every file still to be moved, so it never needs to go
+    // back into the index
+    let mut parts: HashMap<usize, Vec<FileSpan>> = HashMap::new();
+    for span in &disk.files {
+        parts.entry(span.id).or_default().push(*span);
+    }
+    let mut files: Vec<Vec<FileSpan>> = parts.into_values().collect();
+    files.sort_unstable_by_key(|parts| Reverse(parts[0].start));
+    let mut index = FreeIndex::new(&disk.free);
+
+    let mut moves = Vec::new();
+    let mut spans = Vec::with_capacity(disk.files.len());
+    for parts in files {
+        let len = parts.iter().map(|span| span.len).sum();
+        let Some(start) = index.take(fit, len, parts[0].start) else {
+            spans.extend(parts);
+            continue;
+        };
+        let mut to = start;
+        for span in &parts {
+            moves.push(Move {
+                id: span.id,
+                from: span.start,
+                to,
+                len: span.len,
+            });
+            to += span.len;
+        }
+        spans.push(FileSpan {
+            id: parts[0].id,
+            start,
+            len,
+        });
+    }
+
+    Compaction {
+        disk: Disk::from_files(spans, disk.size),
+        moves,
+    }
+}
+
+/// The file spans of a disk being rearranged, recording every move.
+struct Layout {
+    /// Spans by start.
+    spans: BTreeMap<usize, FileSpan>,
+    /// Free span lengths by start, merged with their neighbours.
+    free: BTreeMap<usize, usize>,
+    /// The same spans as `(len, start)`.
+    free_by_len: BTreeSet<(usize, usize)>,
+    /// The spans of every file as `(offset in the file, start, len)`.
+    parts: HashMap<usize, Vec<(usize, usize, usize)>>,
+    moves: Vec<Move>,
+}
+
+impl Layout {
+    fn new(disk: &Disk) -> Self {
+        let mut parts: HashMap<usize, Vec<(usize, usize, usize)>> = HashMap::new();
+        for span in &disk.files {
+            let file = parts.entry(span.id).or_default();
+            let offset = file.last().map_or(0, |&(offset, _, len)| offset + len);
+            file.push((offset, span.start, span.len));
+        }
+        Layout {
+            spans: disk.files.iter().map(|span| (span.start, *span)).collect(),
+            free: disk
+                .free
+                .iter()
+                .map(|span| (span.start, span.len))
+                .collect(),
+            free_by_len: disk
+                .free
+                .iter()
+                .map(|span| (span.len, span.start))
+                .collect(),
+            parts,
+            moves: Vec::new(),
+        }
+    }
+
+    fn add_free(&mut self, start: usize, len: usize) {
+        self.free.insert(start, len);
+        self.free_by_len.insert((len, start));
+    }
+
+    fn remove_free(&mut self, start: usize) -> Option<usize> {
+        let len = self.free.remove(&start)?;
+        self.free_by_len.remove(&(len, start));
+        Some(len)
+    }
+
+    /// Marks `start..start + len` free, merging it with free neighbours.
+    fn release(&mut self, mut start: usize, mut len: usize) {
+        if let Some((&before, &before_len)) = self.free.range(..start).next_back()
+            && before + before_len == start
+        {
+            self.remove_free(before);
+            start = before;
+            len += before_len;
+        }
+        if let Some(after_len) = self.remove_free(start + len) {
+            len += after_len;
+        }
+        self.add_free(start, len);
+    }
+
+    /// Marks the free blocks `start..start + len` used.
+    fn occupy(&mut self, start: usize, len: usize) {
+        let (&hole, &hole_len) = self
+            .free
+            .range(..=start)
+            .next_back()
+            .filter(|&(&hole, &hole_len)| hole + hole_len >= start + len)
+            .expect("Move lands on blocks in use");
+        self.remove_free(hole);
+        if start > hole {
+            self.add_free(hole, start - hole);
+        }
+        if hole + hole_len > start + len {
+            self.add_free(start + len, hole + hole_len - start - len);
+        }
+    }
+
+    /// Moves the whole span starting at `start` to `to`.
+    fn move_span(&mut self, start: usize, to: usize) {
+        // The source is let go first, as the two may overlap
+        let span = self.spans.remove(&start).expect("No span to move");
+        self.release(start, span.len);
+        self.occupy(to, span.len);
+        self.spans.insert(to, FileSpan { start: to, ..span });
+
+        let part = self
+            .parts
+            .get_mut(&span.id)
+            .expect("Unknown file")
+            .iter_mut()
+            .find(|(_, part_start, _)| *part_start == start)
+            .expect("Unknown span");
+        part.1 = to;
+
+        self.moves.push(Move {
+            id: span.id,
+            from: start,
+            to,
+            len: span.len,
+        });
+    }
+
+    /// The parts of a file in the order of their offsets.
+    fn parts(&self, id: usize) -> Vec<(usize, usize, usize)> {
+        let mut parts = self.parts[&id].clone();
+        parts.sort_unstable();
+        parts
+    }
+
+    fn is_split(&self, id: usize) -> bool {
+        self.parts(id)
+            .windows(2)
+            .any(|pair| pair[0].1 + pair[0].2 != pair[1].1)
+    }
+
+    /// The moves, as `(start, to)`, that join the split file `id` in the
+    /// place needing the fewest blocks moved. Keeping one of its parts where
+    /// it is works when the rest of the file fits around it, on blocks that
+    /// are free or already hold the right part. Otherwise the file moves
+    /// whole into the shortest free span that holds it.
+    fn join_plan(&self, id: usize, size: usize) -> Option<Vec<(usize, usize)>> {
+        let parts = self.parts(id);
+        let len: usize = parts.iter().map(|&(_, _, len)| len).sum();
+        let offsets: HashMap<usize, usize> = parts
+            .iter()
+            .map(|&(offset, start, _)| (start, offset))
+            .collect();
+
+        // `(blocks moved, where the file starts)`
+        let mut best: Option<(usize, usize)> = self
+            .free_by_len
+            .range((len, 0)..)
+            .next()
+            .map(|&(_, to)| (len, to));
+        for &(offset, start, _) in &parts {
+            let Some(to) = start.checked_sub(offset).filter(|&to| to + len <= size) else {
+                continue;
+            };
+            let clear_before = self
+                .spans
+                .range(..to)
+                .next_back()
+                .is_none_or(|(_, span)| span.start + span.len <= to);
+            let mut in_place = 0;
+            let fits = clear_before
+                && self.spans.range(to..to + len).all(|(&start, span)| {
+                    in_place += span.len;
+                    span.id == id && offsets.get(&start) == Some(&(start - to))
+                });
+            if fits && best.is_none_or(|(moved, _)| len - in_place < moved) {
+                best = Some((len - in_place, to));
+            }
+        }
+
+        let (_, to) = best?;
+        Some(
+            parts
+                .into_iter()
+                .filter(|&(offset, start, _)| start != to + offset)
+                .map(|(offset, start, _)| (start, to + offset))
+                .collect(),
+        )
+    }
+
+    /// Slides every span left over the free space before it, keeping the
+    /// order of the spans.
+    fn slide(&mut self) {
+        let starts: Vec<usize> = self.spans.keys().copied().collect();
+        let mut to = 0;
+        for start in starts {
+            let len = self.spans[&start].len;
+            if start > to {
+                self.move_span(start, to);
+            }
+            to += len;
+        }
+    }
+
+    /// The disk as it now stands, with the pieces of a file that have come
+    /// together joined up.
+    fn disk(&self, size: usize) -> Disk {
+        let disk = Disk::from_files(self.spans.values().copied().collect(), size);
+        Disk::from_files(disk.runs(), size)
+    }
+}
+
+/// Joins every split file, trying to move few blocks. Each one goes where
+/// joining it moves the fewest blocks: around one of its parts that can stay
+/// where it is, or else into the shortest free span long enough for it.
+/// Files that are already contiguous stay put, unless the disk is too full
+/// for a split file and has to be packed to make room.
+fn join_split_files(disk: &Disk) -> Result<Layout, String> {
+    let mut layout = Layout::new(disk);
+    let used: usize = disk.files.iter().map(|span| span.len).sum();
+
+    let mut seen = HashSet::new();
+    let split: Vec<usize> = disk
+        .files
+        .iter()
+        .map(|span| span.id)
+        .filter(|&id| seen.insert(id) && layout.is_split(id))
+        .collect();
+    for id in split {
+        let plan = match layout.join_plan(id, disk.size) {
+            Some(plan) => plan,
+            None => {
+                // Packing the disk gathers all free space at the end
+                layout.slide();
+                if used == disk.size {
+                    return Err(format!(
+                        "Defragmenting split file {} needs a free block",
+                        id
+                    ));
+                }
+                match layout.join_plan(id, disk.size) {
+                    Some(plan) => plan,
+                    None => return Ok(defragment_blocks(disk.size, layout)),
+                }
+            }
+        };
+        for (start, to) in plan {
+            layout.move_span(start, to);
+        }
+    }
+
+    // Every file is contiguous now, so its pieces become one span and move
+    // together from here on
+    let moves = std::mem::take(&mut layout.moves);
+    layout = Layout::new(&layout.disk(disk.size));
+    layout.moves = moves;
+    Ok(layout)
+}
+
+fn defragment(disk: &Disk) -> Result<Compaction, String> {
+    let layout = join_split_files(disk)?;
+    Ok(Compaction {
+        disk: layout.disk(disk.size),
+        moves: layout.moves,
+    })
+}
+
+/// Joins the split files, then packs the disk from the left: a hole is
+/// filled with the longest file that has to move anyway, as it has blocks
+/// past the end of the packed area, and only when none fits does the file
+/// after the hole slide left over it.
+fn pack(disk: &Disk) -> Result<Compaction, String> {
+    let mut layout = join_split_files(disk)?;
+    let used: usize = disk.files.iter().map(|span| span.len).sum();
+
+    // Files that cannot stay where they are, as `(len, start)`
+    let mut outside: BTreeSet<(usize, usize)> = layout
+        .spans
+        .values()
+        .filter(|span| span.start + span.len > used)
+        .map(|span| (span.len, span.start))
+        .collect();
+
+    // Everything before `to` is packed
+    let mut to = 0;
+    while let Some((&start, &span)) = layout.spans.range(to..).next() {
+        if start == to {
+            outside.remove(&(span.len, start));
+            to += span.len;
+            continue;
+        }
+
+        // The longest file that fits in the hole, the last of equals, or
+        // else the file after the hole
+        let hole = start - to;
+        let (len, from) = outside
+            .range(..=(hole, usize::MAX))
+            .next_back()
+            .copied()
+            .unwrap_or((span.len, start));
+        outside.remove(&(len, from));
+        layout.move_span(from, to);
+        to += len;
+    }
+
+    Ok(Compaction {
+        disk: layout.disk(disk.size),
+        moves: layout.moves,
+    })
+}
+
+/// Finishes defragmenting a packed disk one block at a time, for split
+/// files too long for the free space. The first free block is used to
+/// swap blocks, so each block moves at most twice.
+fn defragment_blocks(size: usize, layout: Layout) -> Layout {
+    let used: usize = layout.spans.values().map(|span| span.len).sum();
+    let mut blocks = vec![None; used + 1];
+    for span in layout.spans.values() {
+        blocks[span.start..span.start + span.len].fill(Some(span.id));
+    }
+
+    // The files in the order they first appear, packed
+    let mut target = Vec::with_capacity(used + 1);
+    let mut seen = HashSet::new();
+    for span in layout.spans.values() {
+        if seen.insert(span.id) {
+            let len: usize = layout.parts[&span.id].iter().map(|&(_, _, len)| len).sum();
+            target.extend(std::iter::repeat_n(Some(span.id), len));
+        }
+    }
+    target.push(None);
+
+    // Blocks not where they belong, checked again when taken
+    let mut misplaced: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if let Some(id) = *block
+            && target[i] != *block
+        {
+            misplaced.entry(id).or_default().push(i);
+        }
+    }
+
+    let mut moves = Vec::new();
+    let mut free = used;
+    for i in 0..used {
+        if blocks[i] == target[i] {
+            continue;
+        }
+        // Everything before `i` is in place, so the free block is after it
+        if let Some(id) = blocks[i] {
+            moves.push(Move {
+                id,
+                from: i,
+                to: free,
+                len: 1,
+            });
+            blocks[free] = blocks[i].take();
+            if target[free] != blocks[free] {
+                misplaced.entry(id).or_default().push(free);
+            }
+        }
+
+        let id = target[i].expect("Packed area has a free block");
+        let from = loop {
+            let j = misplaced
+                .get_mut(&id)
+                .and_then(Vec::pop)
+                .expect("No block left for the file");
+            if j > i && blocks[j] == Some(id) && target[j] != blocks[j] {
+                break j;
+            }
+        };
+        moves.push(Move {
+            id,
+            from,
+            to: i,
+            len: 1,
+        });
+        blocks[i] = blocks[from].take();
+        free = from;
+    }
+
+    let mut files = Vec::new();
+    let mut start = 0;
+    for run in target[..used].chunk_by(|a, b| a == b) {
+        files.push(FileSpan {
+            id: run[0].expect("Packed area has a free block"),
+            start,
+            len: run.len(),
+        });
+        start += run.len();
+    }
+    let mut packed = Layout::new(&Disk::from_files(files, size));
+    packed.moves = layout.moves;
+    packed.moves.extend(moves);
+    packed
+}
+
+fn part1(disk: &Disk, width: Width) -> u128 {
+    compact(disk, Strategy::Blocks)
+        .and_then(|compaction| compaction.disk.checksum(width))
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn part2(disk: &Disk, width: Width) -> u128 {
+    compact(disk, Strategy::Files(Fit::First))
+        .and_then(|compaction| compaction.disk.checksum(width))
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn report(disk: &Disk, strategy: Strategy, width: Width) {
+    let compaction = match compact(disk, strategy) {
+        Ok(compaction) => compaction,
+        Err(e) => {
+            println!("{:>10}: {}", strategy.name(), e);
+            return;
+        }
+    };
+    println!(
+        "{:>10}: checksum {}, {} blocks moved, {} holes left, {} split files",
+        strategy.name(),
+        compaction
+            .disk
+            .checksum(width)
+            .unwrap_or_else(|e| panic!("{}", e)),
+        compaction.blocks_moved(),
+        compaction.disk.holes(),
+        compaction.disk.split_files()
+    );
+}
+
+/// Usage of the disk before and after every compaction strategy.
+fn analyse(disk: &Disk) {
+    Usage::print_header();
+    disk.usage().print_row("before");
+    for strategy in Strategy::ALL {
+        match compact(disk, strategy) {
+            Ok(compaction) => compaction.disk.usage().print_row(strategy.name()),
+            Err(e) => println!("{:>10}  {}", strategy.name(), e),
+        }
+    }
+}
+
+/// The longest layout printed in full; longer disks are cut off.
+const LAYOUT_WIDTH: usize = 120;
+
+/// The disk in the puzzle's notation, such as `00...111...2`. Only the last
+/// digit of file ids past 9 is shown.
+fn render(blocks: &[FileBlock]) -> String {
+    let mut layout: String = blocks
+        .iter()
+        .take(LAYOUT_WIDTH)
+        .map(|block| match block {
+            FileBlock::FileId(id) => char::from_digit((id % 10) as u32, 10).unwrap(),
+            FileBlock::FreeSpace => '.',
+        })
+        .collect();
+    if blocks.len() > LAYOUT_WIDTH {
+        layout += &format!("... (+{} blocks)", blocks.len() - LAYOUT_WIDTH);
+    }
+    layout
+}
+
+/// Replays a compaction from the original disk, printing the layout after
+/// every move. Block by block compaction is shown one block at a time, as in
+/// the puzzle.
+fn print_steps(disk: &Disk, strategy: Strategy) -> Result<(), String> {
+    let compaction = compact(disk, strategy)?;
+    let mut blocks = disk.blocks();
+    println!("{}", render(&blocks));
+    for m in &compaction.moves {
+        if strategy == Strategy::Blocks {
+            for single in m.single_blocks() {
+                single.apply(&mut blocks);
+                println!("{}", render(&blocks));
+            }
         } else {
-            free_space = true;
-            file_blocks.extend(std::iter::repeat(FileBlock::FileId(file_id)).take(cdigit));
-            file_id += 1;
+            m.apply(&mut blocks);
+            println!("{}", render(&blocks));
+        }
+    }
+    assert!(
+        blocks == compaction.disk.blocks(),
+        "Replaying the moves does not give the compacted disk"
+    );
+    Ok(())
+}
+
+/// The blocks of `disk` after applying `moves` to it.
+fn replay(disk: &Disk, moves: &[Move]) -> Vec<FileBlock> {
+    let mut blocks = disk.blocks();
+    for m in moves {
+        m.apply(&mut blocks);
+    }
+    blocks
+}
+
+/// Checks that every strategy's moves lead to the disk it reports, and that
+/// its span checksum matches the replayed blocks, also defragmenting the split
+/// files block by block compaction leaves behind.
+fn replays(disk: &Disk) -> bool {
+    let mut ok = true;
+    let split = compact_blocks(disk).disk;
+    let runs = Strategy::ALL
+        .into_iter()
+        .map(|strategy| (strategy, disk))
+        .chain([(Strategy::Defragment, &split), (Strategy::Pack, &split)]);
+    for (strategy, disk) in runs {
+        // A disk that cannot be compacted has no moves to check
+        let Ok(compaction) = compact(disk, strategy) else {
+            continue;
+        };
+        let blocks = replay(disk, &compaction.moves);
+        if blocks != compaction.disk.blocks() {
+            println!(
+                "The {} moves do not give its compacted disk",
+                strategy.name()
+            );
+            ok = false;
+        } else if compaction.disk.checksum(Width::U128) != Ok(block_checksum(&blocks)) {
+            println!(
+                "The {} span checksum differs from its blocks",
+                strategy.name()
+            );
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// The move log as CSV.
+fn print_moves(disk: &Disk, strategy: Strategy) -> Result<(), String> {
+    let moves = compact(disk, strategy)?.moves;
+    println!("id,from,to,len");
+    for m in moves {
+        println!("{},{},{},{}", m.id, m.from, m.to, m.len);
+    }
+    Ok(())
+}
+
+/// Bytes per block in a disk image: the file id as a little-endian u64.
+const IMAGE_RECORD: usize = 8;
+
+/// The record of a free block in a disk image.
+const IMAGE_FREE: u64 = u64::MAX;
+
+/// Writes the disk as an image of one record per block, without building
+/// the block view.
+fn write_image(disk: &Disk, out: impl Write) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    let free = IMAGE_FREE.to_le_bytes();
+    let mut end = 0;
+    for span in &disk.files {
+        for _ in end..span.start {
+            out.write_all(&free)?;
+        }
+        let id = u64::try_from(span.id)
+            .ok()
+            .filter(|&id| id != IMAGE_FREE)
+            .ok_or_else(|| io::Error::other(format!("File id {} has no record", span.id)))?;
+        for _ in 0..span.len {
+            out.write_all(&id.to_le_bytes())?;
+        }
+        end = span.start + span.len;
+    }
+    for _ in end..disk.size {
+        out.write_all(&free)?;
+    }
+    out.flush()
+}
+
+/// Reads a disk image back into spans, one run of equal records at a time.
+fn read_image(input: impl Read) -> Result<Disk, String> {
+    let mut input = BufReader::new(input);
+    let mut files: Vec<FileSpan> = Vec::new();
+    let mut size = 0;
+    let mut record = [0; IMAGE_RECORD];
+    loop {
+        let read = input
+            .read(&mut record)
+            .map_err(|e| format!("Failed to read image: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        if read < IMAGE_RECORD {
+            input
+                .read_exact(&mut record[read..])
+                .map_err(|_| format!("Image ends inside the record of block {}", size))?;
+        }
+
+        let id = u64::from_le_bytes(record);
+        if id != IMAGE_FREE {
+            let id = usize::try_from(id)
+                .map_err(|_| format!("File id {} at block {} is too large", id, size))?;
+            match files.last_mut() {
+                Some(last) if last.id == id && last.start + last.len == size => last.len += 1,
+                _ => files.push(FileSpan {
+                    id,
+                    start: size,
+                    len: 1,
+                }),
+            }
+        }
+        size += 1;
+    }
+    Ok(Disk::from_files(files, size))
+}
+
+/// Restores the disk a compaction started from by running its moves
+/// backwards.
+fn undo(compacted: &Disk, moves: &[Move]) -> Disk {
+    let mut blocks = compacted.blocks();
+    for m in moves.iter().rev() {
+        m.reversed().apply(&mut blocks);
+    }
+    Disk::from_blocks(&blocks)
+}
+
+/// Sends the compacted disk through an image and back, then undoes its
+/// moves, checking both give the layouts they started from.
+fn round_trip(disk: &Disk, strategy: Strategy, image: &[u8]) -> Result<(), String> {
+    let compaction = compact(disk, strategy)?;
+    let reloaded = read_image(image)?;
+    if reloaded.size != compaction.disk.size || reloaded.runs() != compaction.disk.runs() {
+        return Err(format!(
+            "The {} image does not reload to its compacted disk",
+            strategy.name()
+        ));
+    }
+    if undo(&reloaded, &compaction.moves).runs() != disk.runs() {
+        return Err(format!(
+            "Undoing the {} moves does not restore the disk",
+            strategy.name()
+        ));
+    }
+    Ok(())
+}
+
+/// Block by block in u128, so the references never overflow.
+fn block_checksum(file_blocks: &[FileBlock]) -> u128 {
+    let mut checksum = 0;
+    for (idx, block) in file_blocks.iter().enumerate() {
+        if let FileBlock::FileId(id) = block {
+            checksum += idx as u128 * *id as u128;
         }
     }
 
+    checksum
+}
+
+/// Block by block compaction, kept as a reference for `--verify`.
+fn reference_part1(mut file_blocks: Vec<FileBlock>) -> u128 {
+    if file_blocks.is_empty() {
+        return 0;
+    }
+
     let mut i = 0;
     let mut j = file_blocks.len() - 1;
 
@@ -59,134 +1392,278 @@ fn part1(input: &Vec<char>) -> usize {
         }
     }
 
-    let mut checksum = 0;
-    for (idx, block) in file_blocks.iter().enumerate() {
+    block_checksum(&file_blocks)
+}
+
+/// Whole-file compaction rescanning the blocks for every file, kept as a
+/// reference for `--verify`. Files are taken right to left by their first
+/// block, and all the blocks of a split file move together.
+fn reference_part2(mut file_blocks: Vec<FileBlock>) -> u128 {
+    // The blocks of every file, in disk order
+    let mut files: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (pos, block) in file_blocks.iter().enumerate() {
         if let FileBlock::FileId(id) = block {
-            checksum += idx * *id;
+            files.entry(*id).or_default().push(pos);
         }
     }
+    let mut files: Vec<(usize, Vec<usize>)> = files.into_iter().collect();
+    files.sort_unstable_by_key(|(_, blocks)| Reverse(blocks[0]));
 
-    checksum
-}
-
-fn part2(input: &Vec<char>) -> usize {
-    let mut file_blocks = Vec::new();
-    let mut file_id = 0;
-    let mut free_space = false;
-
-    for c in input {
-        let cdigit = c.to_digit(10).unwrap_or(0) as usize;
-        if free_space {
-            free_space = false;
-            file_blocks.extend(std::iter::repeat(FileBlock::FreeSpace).take(cdigit));
-        } else {
-            free_space = true;
-            file_blocks.extend(std::iter::repeat(FileBlock::FileId(file_id)).take(cdigit));
-            file_id += 1;
-        }
-    }
-    
-    // Start from the end of the array and move backwards
-    let mut pos = file_blocks.len();
-    
-    // Process each file block from right to left
-    while pos > 0 {
-        // Find the position of the rightmost file block
-        // Decrement pos first to point to the current element
-        pos -= 1; 
-        while pos > 0 && matches!(file_blocks[pos], FileBlock::FreeSpace) {
-            pos -= 1;
-        }
-        
-        if matches!(file_blocks[pos], FileBlock::FreeSpace) { // If we landed on a free space at index 0
-            break;
-        }
-        
-        // Find the start position of the current file block
-        let file_end = pos; // pos is now the end of the current file block
-        let mut file_start = file_end;
-        while file_start > 0 {
-            if let FileBlock::FileId(current_id) = file_blocks[file_start - 1] {
-                if let FileBlock::FileId(prev_id) = file_blocks[file_start] {
-                    if current_id == prev_id { // Check if it's the same file ID
-                        file_start -= 1;
-                    } else {
-                        break; // Different file ID, so this is the start of the current file
-                    }
-                } else {
-                    break; // Previous block is FreeSpace, so this is the start of the current file
+    for (id, blocks) in files {
+        // Find the leftmost run of free blocks before the file that fits it
+        let mut run = 0;
+        let mut free_start = None;
+        for (pos, block) in file_blocks[..blocks[0]].iter().enumerate() {
+            if *block == FileBlock::FreeSpace {
+                run += 1;
+                if run == blocks.len() {
+                    free_start = Some(pos + 1 - run);
+                    break;
                 }
             } else {
-                break; // Previous block is FreeSpace, so this is the start of the current file
+                run = 0;
             }
         }
-        
-        // Get the file ID and calculate its length
-        let current_file_id = match file_blocks[file_start] {
-            FileBlock::FileId(id) => id,
-            FileBlock::FreeSpace => unreachable!(), // Should not happen if logic is correct
-        };
-        let file_length = file_end - file_start + 1;
-        
-        // Find the leftmost contiguous free space that can fit the file
-        let mut free_start = 0;
-        // Removed `found_suitable_spot` as it was unused
-        while free_start < file_start {
-            // Find the start of a free space region
-            while free_start < file_start && !matches!(file_blocks[free_start], FileBlock::FreeSpace) {
-                free_start += 1;
-            }
-            
-            if free_start >= file_start {
-                break;
-            }
-            
-            // Calculate the length of this free space region
-            let mut free_end = free_start;
-            while free_end < file_start && matches!(file_blocks[free_end], FileBlock::FreeSpace) {
-                free_end += 1;
-            }
-            
-            // Check if this free space can fit our file
-            if free_end - free_start >= file_length {
-                // We found a suitable spot - move the file there
-                for i in 0..file_length {
-                    file_blocks[free_start + i] = FileBlock::FileId(current_file_id);
-                }
-                
-                // Mark the old positions as free space
-                for i in file_start..=file_end {
-                    file_blocks[i] = FileBlock::FreeSpace;
-                }
-                
-                // Removed assignment to `found_suitable_spot` as it was unused
-                // Break out of the free space search
-                break;
+
+        if let Some(free_start) = free_start {
+            for &pos in &blocks {
+                file_blocks[pos] = FileBlock::FreeSpace;
             }
-            
-            // Move to the next potential free space region
-            free_start = free_end;
+            file_blocks[free_start..free_start + blocks.len()].fill(FileBlock::FileId(id));
         }
-        
-        // Move left to the next file
-        pos = file_start;
     }
 
-    let mut checksum = 0;
-    for (idx, block) in file_blocks.iter().enumerate() {
-        if let FileBlock::FileId(id) = block {
-            checksum += idx * *id;
-        }
-    }
+    block_checksum(&file_blocks)
+}
 
-    checksum
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|pos| args.get(pos + 1))
+        .map(String::as_str)
 }
 
 fn main() {
     println!("--- Day 9: Disk Fragmenter ---");
-    let input = read_input();
-    let result = part1(&input);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `--input PATH` reads another disk map, `--format dense|csv|json`
+    // overrides the format guessed from its contents (and is needed when
+    // lines of several digits leave it ambiguous), and `--image PATH` loads
+    // a disk image written by `--export` instead
+    let disk = if let Some(path) = arg_value(&args, "--image") {
+        read_image(File::open(path).expect("Failed to open image"))
+            .unwrap_or_else(|e| panic!("{}", e))
+    } else {
+        let path = arg_value(&args, "--input").unwrap_or("input/day9.txt");
+        let text = read_input(path);
+        let format = arg_value(&args, "--format")
+            .map_or_else(|| Format::detect(&text), Format::from_str)
+            .unwrap_or_else(|e| panic!("{}", e));
+        Disk::parse(&text, format).unwrap_or_else(|e| panic!("{}", e))
+    };
+
+    // `--wide` sums checksums in u128 for disks too big for usize
+    let width = if args.iter().any(|a| a == "--wide") {
+        Width::U128
+    } else {
+        Width::Usize
+    };
+
+    let result = part1(&disk, width);
     println!("Part 1 Solution: {}", result);
-    let result = part2(&input);
+    let result = part2(&disk, width);
     println!("Part 2 Solution: {}", result);
+
+    // `--strategy NAME|all` compacts with other strategies and reports on them
+    if let Some(name) = arg_value(&args, "--strategy") {
+        if name == "all" {
+            for strategy in Strategy::ALL {
+                report(&disk, strategy, width);
+            }
+        } else {
+            report(
+                &disk,
+                Strategy::from_str(name).unwrap_or_else(|e| panic!("{}", e)),
+                width,
+            );
+        }
+    }
+
+    // `--steps STRATEGY` draws the disk after every move, `--moves STRATEGY`
+    // prints the move log
+    if let Some(name) = arg_value(&args, "--steps") {
+        let strategy = Strategy::from_str(name).unwrap_or_else(|e| panic!("{}", e));
+        if let Err(e) = print_steps(&disk, strategy) {
+            println!("{}", e);
+        }
+    }
+    if let Some(name) = arg_value(&args, "--moves") {
+        let strategy = Strategy::from_str(name).unwrap_or_else(|e| panic!("{}", e));
+        if let Err(e) = print_moves(&disk, strategy) {
+            println!("{}", e);
+        }
+    }
+
+    // `--export PATH` writes the disk compacted by `--export-strategy`
+    // (first-fit by default) as an image, then checks it reloads and that
+    // undoing the moves restores the disk
+    if let Some(path) = arg_value(&args, "--export") {
+        let strategy = arg_value(&args, "--export-strategy")
+            .map_or(Ok(Strategy::Files(Fit::First)), Strategy::from_str)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let compacted = compact(&disk, strategy)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .disk;
+        write_image(
+            &compacted,
+            File::create(path).expect("Failed to create image"),
+        )
+        .expect("Failed to write image");
+        println!(
+            "Wrote {} blocks compacted with {} to {}",
+            compacted.size,
+            strategy.name(),
+            path
+        );
+
+        let image = fs::read(path).expect("Failed to read image");
+        round_trip(&disk, strategy, &image).unwrap_or_else(|e| panic!("{}", e));
+        println!("The image reloads and undoes to the original disk");
+    }
+
+    // `--analyse` summarises disk usage before and after compaction
+    if args.iter().any(|a| a == "--analyse") {
+        analyse(&disk);
+    }
+
+    // `--verify` checks the span-based compaction against the per-block one,
+    // replays every move log and round-trips every compacted disk through an
+    // image
+    if args.iter().any(|a| a == "--verify") {
+        let blocks = disk.blocks();
+        let expected = (reference_part1(blocks.clone()), reference_part2(blocks));
+        if expected == (part1(&disk, Width::U128), part2(&disk, Width::U128)) {
+            println!("Span and block compaction agree");
+        } else {
+            println!("Block compaction gives {:?}", expected);
+        }
+        if replays(&disk) {
+            println!("All move logs replay to the compacted disks and checksums");
+        }
+
+        let mut ok = true;
+        for strategy in Strategy::ALL {
+            let Ok(compaction) = compact(&disk, strategy) else {
+                continue;
+            };
+            let mut image = Vec::new();
+            write_image(&compaction.disk, &mut image).expect("Failed to write image");
+            if let Err(e) = round_trip(&disk, strategy, &image) {
+                println!("{}", e);
+                ok = false;
+            }
+        }
+        if ok {
+            println!("All images reload and all moves undo to the original disk");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small xorshift generator, enough to draw random disks.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// A dense map, a CSV map or, half the time, a JSON layout whose files
+    /// may be split, as those are the layouts the rules are hardest on.
+    fn random_disk(rng: &mut Rng) -> Disk {
+        let (text, format) = match rng.below(4) {
+            0 => {
+                let digits: String = (0..1 + rng.below(25))
+                    .map(|_| char::from_digit(rng.below(10) as u32, 10).unwrap())
+                    .collect();
+                (digits, Format::Dense)
+            }
+            1 => {
+                let lengths: Vec<String> = (0..1 + rng.below(15))
+                    .map(|_| rng.below(15).to_string())
+                    .collect();
+                (lengths.join(","), Format::Csv)
+            }
+            _ => {
+                let size = 1 + rng.below(30);
+                let ids = 1 + rng.below(4);
+                let fill = 8 + rng.below(12);
+                let blocks: Vec<Option<usize>> = (0..size)
+                    .map(|_| (rng.below(20) < fill).then(|| rng.below(ids)))
+                    .collect();
+
+                // Runs of one file are cut into pieces, which the layout
+                // joins again, and a file may have several runs
+                let mut files = Vec::new();
+                let mut start = 0;
+                for run in blocks.chunk_by(|a, b| a == b) {
+                    if let Some(id) = run[0] {
+                        let mut offset = 0;
+                        while offset < run.len() {
+                            let len = 1 + rng.below(run.len() - offset);
+                            files.push(format!(
+                                r#"{{"id":{},"start":{},"len":{}}}"#,
+                                id,
+                                start + offset,
+                                len
+                            ));
+                            offset += len;
+                        }
+                    }
+                    start += run.len();
+                }
+                let layout = format!(r#"{{"size":{},"files":[{}]}}"#, size, files.join(","));
+                (layout, Format::Json)
+            }
+        };
+        Disk::parse(&text, format).unwrap_or_else(|e| panic!("{}: {}", e, text))
+    }
+
+    #[test]
+    fn compaction_matches_the_block_references() {
+        let mut rng = Rng(0x2024_0009);
+        for _ in 0..400 {
+            let disk = random_disk(&mut rng);
+            let blocks = disk.blocks();
+            assert_eq!(part1(&disk, Width::U128), reference_part1(blocks.clone()));
+            assert_eq!(part2(&disk, Width::U128), reference_part2(blocks));
+            assert!(replays(&disk));
+        }
+    }
+
+    #[test]
+    fn compacted_images_reload_and_undo() {
+        let mut rng = Rng(0x2024_0047);
+        for _ in 0..200 {
+            let disk = random_disk(&mut rng);
+            for strategy in Strategy::ALL {
+                // A disk too full to defragment has nothing to export
+                let Ok(compaction) = compact(&disk, strategy) else {
+                    continue;
+                };
+                let mut image = Vec::new();
+                write_image(&compaction.disk, &mut image).unwrap();
+                assert_eq!(image.len(), disk.size * IMAGE_RECORD);
+                assert_eq!(round_trip(&disk, strategy, &image), Ok(()));
+            }
+        }
+    }
 }